@@ -0,0 +1,206 @@
+use super::{elevation, track};
+use crate::models::{Duration, Record, Session};
+use uom::si::{
+    f64::{Length, Mass, Time, Velocity},
+    length::meter,
+    mass::kilogram,
+    time::second,
+    u16::Length as Length_u16,
+    velocity::meter_per_second,
+};
+
+// Slower movement than this, in meters per second, does not count as moving
+pub(super) const MOVING_SPEED: f64 = 0.5;
+// Gaps between samples longer than this, in seconds, do not count as moving
+const MAX_GAP: f64 = 10.;
+// Age used for the heart rate based calorie estimate, since it is not known
+const REFERENCE_AGE: f64 = 35.;
+
+/// Fills in the session values the device did not record, from the record.
+pub fn fill(session: &mut Session, record: &Record, weight: Option<Mass>) {
+    let seconds: Vec<f64> = record.duration.iter().map(Duration::as_secs_f64).collect();
+    let speed = speed(record, &seconds);
+
+    if session.duration == Duration::default() {
+        session.duration = record.duration.last().copied().unwrap_or_default();
+    }
+
+    if session.duration_active == Duration::default() {
+        session.duration_active = Duration::from_secs_f64(moving_time(&seconds, &speed));
+    }
+
+    if session.distance.is_none() {
+        session.distance = distance(record);
+    }
+
+    if session.speed_avg.is_none() && session.duration_active != Duration::default() {
+        session.speed_avg = session
+            .distance
+            .map(|x| x / Time::new::<second>(session.duration_active.as_secs_f64()));
+    }
+
+    if session.speed_max.is_none() {
+        session.speed_max = speed
+            .iter()
+            .flatten()
+            .copied()
+            .fold(None, |max: Option<f64>, x| {
+                Some(max.map_or(x, |y| y.max(x)))
+            })
+            .map(Velocity::new::<meter_per_second>);
+    }
+
+    if session.heartrate_avg.is_none() {
+        session.heartrate_avg = average(&record.heartrate).map(|x| x as u8);
+    }
+    if session.heartrate_max.is_none() {
+        session.heartrate_max = record.heartrate.iter().flatten().max().copied();
+    }
+
+    if session.power_avg.is_none() {
+        session.power_avg = average(&record.power).map(|x| x as u16);
+    }
+    if session.power_max.is_none() {
+        session.power_max = record.power.iter().flatten().max().copied();
+    }
+
+    if session.cadence_avg.is_none() {
+        session.cadence_avg = average(&record.cadence).map(|x| x as u8);
+    }
+    if session.cadence_max.is_none() {
+        session.cadence_max = record.cadence.iter().flatten().max().copied();
+    }
+
+    if session.ascent.is_none() || session.descent.is_none() {
+        let altitude: Vec<Option<f64>> = record
+            .altitude
+            .iter()
+            .map(|x| x.map(|y| y.get::<meter>()))
+            .collect();
+
+        if let Some((ascent, descent)) = elevation::ascent_descent(&elevation::smooth(&altitude)) {
+            session.ascent = session
+                .ascent
+                .or_else(|| Some(Length_u16::new::<meter>(ascent.get::<meter>() as u16)));
+            session.descent = session
+                .descent
+                .or_else(|| Some(Length_u16::new::<meter>(descent.get::<meter>() as u16)));
+        }
+    }
+
+    if session.calories.is_none() {
+        session.calories = calories(record, &seconds, weight).map(|x| x as u16);
+    }
+
+    if session.nec_lat.is_none()
+        || session.nec_lon.is_none()
+        || session.swc_lat.is_none()
+        || session.swc_lon.is_none()
+    {
+        track::set_bounds(session, record);
+    }
+}
+
+/// Speed per sample, from the device or from the distance increments.
+fn speed(record: &Record, seconds: &[f64]) -> Vec<Option<f64>> {
+    (0..seconds.len())
+        .map(|i| match record.speed[i] {
+            Some(x) => Some(x.get::<meter_per_second>()),
+            None if i > 0 => match (record.distance[i], record.distance[i - 1]) {
+                (Some(x), Some(y)) => {
+                    Some((x - y).get::<meter>() / (seconds[i] - seconds[i - 1]).max(1.))
+                }
+                _ => None,
+            },
+            None => None,
+        })
+        .collect()
+}
+
+/// Time spent moving, in seconds. Without any speed information, only gaps
+/// in the recording are left out.
+fn moving_time(seconds: &[f64], speed: &[Option<f64>]) -> f64 {
+    let has_speed = speed.iter().any(Option::is_some);
+
+    (1..seconds.len())
+        .map(|i| (seconds[i] - seconds[i - 1], speed[i]))
+        .filter(|(gap, _)| *gap <= MAX_GAP)
+        .filter(|(_, speed)| !has_speed || speed.is_some_and(|x| x >= MOVING_SPEED))
+        .map(|(gap, _)| gap)
+        .sum()
+}
+
+/// Distance from the device, or from the track if the device has none.
+fn distance(record: &Record) -> Option<Length> {
+    if let Some(x) = record.distance.iter().rev().flatten().next() {
+        return Some(*x);
+    }
+
+    let points: Vec<(f64, f64)> = record
+        .lat
+        .iter()
+        .zip(record.lon.iter())
+        .filter_map(|x| match x {
+            (Some(lat), Some(lon)) => Some((*lat, *lon)),
+            _ => None,
+        })
+        .collect();
+
+    if points.len() < 2 {
+        return None;
+    }
+
+    Some(Length::new::<meter>(
+        points
+            .windows(2)
+            .map(|x| track::haversine(x[0], x[1]))
+            .sum(),
+    ))
+}
+
+fn average<T: Copy + Into<f64>>(values: &[Option<T>]) -> Option<f64> {
+    let (sum, count) = values
+        .iter()
+        .flatten()
+        .fold((0., 0), |(sum, count), x| (sum + (*x).into(), count + 1));
+
+    match count {
+        0 => None,
+        _ => Some(sum / f64::from(count)),
+    }
+}
+
+/// Energy expenditure in kcal. With power, the mechanical work in kJ is
+/// used, since the efficiency of the body cancels out the conversion from
+/// kJ to kcal. Otherwise the heart rate equation by Keytel et al. is used,
+/// averaged over both sexes.
+fn calories(record: &Record, seconds: &[f64], weight: Option<Mass>) -> Option<f64> {
+    let intervals = (1..seconds.len())
+        .map(|i| (i, seconds[i] - seconds[i - 1]))
+        .filter(|(_, gap)| *gap <= MAX_GAP);
+
+    if record.power.iter().any(Option::is_some) {
+        let work: f64 = intervals
+            .map(|(i, gap)| f64::from(record.power[i].unwrap_or_default()) * gap)
+            .sum();
+
+        return Some(work / 1000.);
+    }
+
+    let weight = weight?.get::<kilogram>();
+    if record.heartrate.iter().all(Option::is_none) {
+        return None;
+    }
+
+    let energy: f64 = intervals
+        .filter_map(|(i, gap)| record.heartrate[i].map(|x| (f64::from(x), gap)))
+        .map(|(heartrate, gap)| {
+            let male = -55.0969 + 0.6309 * heartrate + 0.1988 * weight + 0.2017 * REFERENCE_AGE;
+            let female = -20.4022 + 0.4472 * heartrate - 0.1263 * weight + 0.074 * REFERENCE_AGE;
+
+            ((male + female) / 2.).max(0.) / 4.184 * gap / 60.
+        })
+        .sum();
+
+    Some(energy)
+}
@@ -0,0 +1,156 @@
+use super::elevation;
+use crate::models::{Duration, Record};
+use uom::si::{f64::Length, length::meter};
+
+// Descent from the highest point after which a climb is over, in meters
+const DROP_TOLERANCE: f64 = 10.;
+// Altitude above the lowest point at which a climb starts, in meters
+const START_TOLERANCE: f64 = 2.;
+// Minimum length of a climb, in meters
+const MIN_DISTANCE: f64 = 500.;
+// Minimum average gradient of a climb
+const MIN_GRADIENT: f64 = 0.03;
+
+/// Climb categories, from the score of length in meters times the average
+/// gradient in percent.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Category {
+    Cat4,
+    Cat3,
+    Cat2,
+    Cat1,
+    Hc,
+}
+
+impl Category {
+    fn from_score(score: f64) -> Option<Self> {
+        match score {
+            x if x >= 80_000. => Some(Self::Hc),
+            x if x >= 64_000. => Some(Self::Cat1),
+            x if x >= 32_000. => Some(Self::Cat2),
+            x if x >= 16_000. => Some(Self::Cat3),
+            x if x >= 8_000. => Some(Self::Cat4),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Category {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Cat4 => write!(f, "Cat 4"),
+            Self::Cat3 => write!(f, "Cat 3"),
+            Self::Cat2 => write!(f, "Cat 2"),
+            Self::Cat1 => write!(f, "Cat 1"),
+            Self::Hc => write!(f, "HC"),
+        }
+    }
+}
+
+pub struct Climb {
+    /// Index of the first sample in the record
+    pub start: usize,
+    /// Index of the last sample in the record
+    pub end: usize,
+    pub category: Category,
+    pub distance: Length,
+    pub gain: Length,
+    pub gradient: f64,
+    pub duration: Duration,
+    /// Vertical ascent speed in meters per hour
+    pub vam: f64,
+    pub power_avg: Option<u16>,
+}
+
+/// Finds the categorized climbs in the record, in order.
+pub fn climbs(record: &Record) -> Vec<Climb> {
+    let altitude: Vec<Option<f64>> = record
+        .altitude
+        .iter()
+        .map(|x| x.map(|y| y.get::<meter>()))
+        .collect();
+    let altitude = elevation::smooth(&altitude);
+
+    // (index, distance, altitude) of the samples with both values
+    let points: Vec<(usize, f64, f64)> = record
+        .distance
+        .iter()
+        .zip(altitude.iter())
+        .enumerate()
+        .filter_map(|(i, x)| match x {
+            (Some(distance), Some(altitude)) => Some((i, distance.get::<meter>(), *altitude)),
+            _ => None,
+        })
+        .collect();
+
+    let mut climbs = Vec::new();
+    let mut i = 0;
+
+    while i < points.len() {
+        // Follow the climb until it drops too far below its highest point
+        let mut top = i;
+        for j in i + 1..points.len() {
+            if points[j].2 > points[top].2 {
+                top = j;
+            } else if points[top].2 - points[j].2 > DROP_TOLERANCE {
+                break;
+            }
+        }
+
+        if top == i {
+            i += 1;
+            continue;
+        }
+
+        // Start at the last point close to the lowest one, to leave out
+        // the flat leading up to the climb
+        let bottom = (i..top).map(|x| points[x].2).fold(f64::INFINITY, f64::min);
+        let start = (i..top)
+            .rev()
+            .find(|x| points[*x].2 <= bottom + START_TOLERANCE)
+            .unwrap_or(i);
+
+        if let Some(x) = climb(record, points[start], points[top]) {
+            climbs.push(x);
+        }
+
+        i = top;
+    }
+
+    climbs
+}
+
+fn climb(
+    record: &Record,
+    (start, start_distance, start_altitude): (usize, f64, f64),
+    (end, end_distance, end_altitude): (usize, f64, f64),
+) -> Option<Climb> {
+    let distance = end_distance - start_distance;
+    let gain = end_altitude - start_altitude;
+
+    if distance < MIN_DISTANCE || gain / distance < MIN_GRADIENT {
+        return None;
+    }
+
+    let gradient = gain / distance;
+    let category = Category::from_score(distance * gradient * 100.)?;
+    let duration = record.duration[end] - record.duration[start];
+
+    let power = record.power[start..=end].iter().flatten();
+    let power_avg = match power.clone().count() {
+        0 => None,
+        x => Some((power.map(|y| u32::from(*y)).sum::<u32>() / x as u32) as u16),
+    };
+
+    Some(Climb {
+        start,
+        end,
+        category,
+        distance: Length::new::<meter>(distance),
+        gain: Length::new::<meter>(gain),
+        gradient,
+        duration,
+        vam: gain / duration.as_secs_f64().max(1.) * 3600.,
+        power_avg,
+    })
+}
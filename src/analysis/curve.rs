@@ -0,0 +1,269 @@
+use crate::models::{Curve, Duration, Record};
+use uom::si::{f64::Velocity, length::meter, velocity::meter_per_second};
+
+// Gaps between samples longer than this are treated as pauses
+const MAX_GAP: usize = 30;
+
+pub fn mean_maximal(record: &Record) -> Curve {
+    let heartrate = resample(&record.duration, &record.heartrate)
+        .into_iter()
+        .map(|x| x.map(f64::from))
+        .collect::<Vec<Option<f64>>>();
+
+    // Speed is derived from the distance covered each second, so pauses
+    // count towards the duration without adding distance
+    let distance = resample(&record.duration, &record.distance)
+        .into_iter()
+        .scan(None, |acc, x| {
+            if x.is_some() {
+                *acc = x;
+            }
+            Some(*acc)
+        })
+        .collect::<Vec<_>>();
+    let speed = distance
+        .windows(2)
+        .map(|x| match (x[0], x[1]) {
+            (Some(a), Some(b)) => Some((b - a).get::<meter>().max(0.)),
+            _ => Some(0.),
+        })
+        .collect::<Vec<Option<f64>>>();
+
+    Curve {
+        power: if record.power.iter().any(Option::is_some) {
+            best_averages(&power(record))
+                .into_iter()
+                .map_while(|x| x.map(|y| y.round() as u16))
+                .collect()
+        } else {
+            Vec::new()
+        },
+        heartrate: best_averages(&heartrate)
+            .into_iter()
+            .map_while(|x| x.map(|y| y.round() as u8))
+            .collect(),
+        speed: if record.distance.iter().any(Option::is_some) {
+            best_averages(&speed)
+                .into_iter()
+                .map_while(|x| x.map(Velocity::new::<meter_per_second>))
+                .collect()
+        } else {
+            Vec::new()
+        },
+    }
+}
+
+/// Highest average power sustained over the amount of seconds, or None if
+/// the activity is shorter or has no power.
+pub fn best_power(record: &Record, seconds: usize) -> Option<u16> {
+    if record.power.iter().all(Option::is_none) {
+        return None;
+    }
+
+    Windows::new(&power(record))
+        .best(seconds)
+        .map(|x| x.round() as u16)
+}
+
+/// Power for every second, where coasting counts as zero.
+fn power(record: &Record) -> Vec<Option<f64>> {
+    resample(&record.duration, &record.power)
+        .into_iter()
+        .map(|x| Some(x.map(f64::from).unwrap_or_default()))
+        .collect()
+}
+
+/// Spreads the samples of a record field over one-second intervals,
+/// leaving intervals within pauses empty.
+pub fn resample<T: Copy>(duration: &[Duration], values: &[Option<T>]) -> Vec<Option<T>> {
+    let seconds = duration
+        .iter()
+        .map(|x| x.as_secs_f64() as usize)
+        .collect::<Vec<usize>>();
+
+    let length = seconds.last().map(|x| x + 1).unwrap_or_default();
+    let mut resampled = vec![None; length];
+
+    for (i, (start, value)) in seconds.iter().zip(values).enumerate() {
+        let end = seconds.get(i + 1).copied().unwrap_or(start + 1);
+        let end = if end.saturating_sub(*start) > MAX_GAP {
+            start + 1
+        } else {
+            end
+        };
+
+        for x in resampled.iter_mut().take(end).skip(*start) {
+            *x = *value;
+        }
+    }
+
+    resampled
+}
+
+/// Window lengths in seconds the curves are computed and plotted at:
+/// every second up to a minute, then logarithmically spaced.
+pub fn durations() -> impl Iterator<Item = usize> {
+    (1..60).chain(std::iter::successors(Some(60_f64), |x| Some(x * 1.05)).map(|x| x as usize))
+}
+
+/// Finds the highest average over windows of every duration the samples
+/// last, where the value at index `i` belongs to the `i`th of the
+/// durations. Windows containing missing samples are skipped.
+fn best_averages(samples: &[Option<f64>]) -> Vec<Option<f64>> {
+    let windows = Windows::new(samples);
+
+    durations()
+        .take_while(|x| *x <= samples.len())
+        .map(|x| windows.best(x))
+        .collect()
+}
+
+/// Running sums of the samples and of the missing ones, so every window
+/// is summed in constant time.
+struct Windows {
+    sum: Vec<f64>,
+    missing: Vec<usize>,
+}
+
+impl Windows {
+    fn new(samples: &[Option<f64>]) -> Self {
+        let mut sum = Vec::with_capacity(samples.len() + 1);
+        let mut missing = Vec::with_capacity(samples.len() + 1);
+        sum.push(0.);
+        missing.push(0);
+
+        for x in samples {
+            sum.push(sum.last().unwrap() + x.unwrap_or_default());
+            missing.push(missing.last().unwrap() + x.is_none() as usize);
+        }
+
+        Self { sum, missing }
+    }
+
+    /// Highest average over any window of the length without missing
+    /// samples.
+    fn best(&self, window: usize) -> Option<f64> {
+        let length = self.sum.len() - 1;
+        if window == 0 || window > length {
+            return None;
+        }
+
+        (0..=length - window)
+            .filter(|&start| self.missing[start + window] == self.missing[start])
+            .map(|start| self.sum[start + window] - self.sum[start])
+            .fold(None, |acc: Option<f64>, x| {
+                Some(acc.map_or(x, |y| y.max(x)))
+            })
+            .map(|x| x / window as f64)
+    }
+}
+
+/// The best curve values set by any of a user's activities, along with
+/// the id of the activity that set each of them.
+#[derive(Default)]
+pub struct BestCurve {
+    pub power: Vec<(u16, String)>,
+    pub heartrate: Vec<(u8, String)>,
+    pub speed: Vec<(Velocity, String)>,
+}
+
+impl BestCurve {
+    pub fn update(&mut self, id: &str, curve: &Curve, include_speed: bool) {
+        merge(&mut self.power, &curve.power, id);
+        merge(&mut self.heartrate, &curve.heartrate, id);
+        if include_speed {
+            merge(&mut self.speed, &curve.speed, id);
+        }
+    }
+}
+
+fn merge<T: PartialOrd + Copy>(best: &mut Vec<(T, String)>, curve: &[T], id: &str) {
+    for (i, x) in curve.iter().enumerate() {
+        match best.get_mut(i) {
+            Some(y) if *x > y.0 => *y = (*x, id.to_owned()),
+            Some(_) => (),
+            None => best.push((*x, id.to_owned())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Highest average over every window of the length, for comparison.
+    fn brute_force(samples: &[Option<f64>], window: usize) -> Option<f64> {
+        samples
+            .windows(window)
+            .filter_map(|x| x.iter().copied().sum::<Option<f64>>())
+            .fold(None, |acc: Option<f64>, x| {
+                Some(acc.map_or(x, |y| y.max(x)))
+            })
+            .map(|x| x / window as f64)
+    }
+
+    #[test]
+    fn best_averages_exact_at_durations() {
+        let samples = (0..400)
+            .map(|x| Some(((x * 7919) % 251) as f64))
+            .collect::<Vec<Option<f64>>>();
+        let averages = best_averages(&samples);
+
+        let durations = durations().take_while(|x| *x <= 400).collect::<Vec<_>>();
+        assert_eq!(averages.len(), durations.len());
+        for (average, window) in averages.iter().zip(durations) {
+            let expected = brute_force(&samples, window).unwrap();
+            assert!((average.unwrap() - expected).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn durations_increase() {
+        let durations = durations().take(100).collect::<Vec<_>>();
+
+        assert_eq!(&durations[..3], &[1, 2, 3]);
+        assert_eq!(&durations[59..61], &[60, 63]);
+        assert!(durations.windows(2).all(|x| x[0] < x[1]));
+    }
+
+    #[test]
+    fn best_power_over_seconds() {
+        let record = Record {
+            duration: (0..400)
+                .map(|x| Duration::from_secs_f64(x as f64))
+                .collect(),
+            power: (0..400).map(|x| Some(x as u16)).collect(),
+            ..Default::default()
+        };
+
+        // The last 300 seconds average 249.5 W
+        assert_eq!(best_power(&record, 300), Some(250));
+        assert_eq!(best_power(&record, 401), None);
+    }
+
+    #[test]
+    fn best_averages_skips_missing_samples() {
+        let averages = best_averages(&[Some(1.), None, Some(3.)]);
+
+        assert_eq!(averages, vec![Some(3.), None, None]);
+    }
+
+    #[test]
+    fn resample_fills_seconds() {
+        let duration = [0., 1., 3.].iter().map(|x| Duration::from_secs_f64(*x));
+        let resampled = resample(&duration.collect::<Vec<_>>(), &[Some(1), Some(2), Some(3)]);
+
+        assert_eq!(resampled, vec![Some(1), Some(2), Some(2), Some(3)]);
+    }
+
+    #[test]
+    fn resample_leaves_pauses_empty() {
+        let duration = [0., 40.].iter().map(|x| Duration::from_secs_f64(*x));
+        let resampled = resample(&duration.collect::<Vec<_>>(), &[Some(1), Some(2)]);
+
+        assert_eq!(resampled.len(), 41);
+        assert_eq!(resampled[0], Some(1));
+        assert!(resampled[1..40].iter().all(Option::is_none));
+        assert_eq!(resampled[40], Some(2));
+    }
+}
@@ -0,0 +1,86 @@
+/// Picks the points which keep the shape of a line, with the
+/// Largest-Triangle-Three-Buckets algorithm. Returns the indices of at most
+/// `threshold` points, always including the first and the last one.
+pub fn lttb(points: &[(f64, f64)], threshold: usize) -> Vec<usize> {
+    if threshold >= points.len() || threshold < 3 {
+        return (0..points.len()).collect();
+    }
+
+    let mut indices = Vec::with_capacity(threshold);
+    indices.push(0);
+
+    // The first and last points are kept, the others are split into buckets
+    let size = (points.len() - 2) as f64 / (threshold - 2) as f64;
+    let mut previous = 0;
+
+    for bucket in 0..threshold - 2 {
+        let start = (bucket as f64 * size) as usize + 1;
+        let end = ((bucket + 1) as f64 * size) as usize + 1;
+
+        // Average of the next bucket, or the last point
+        let next_start = end;
+        let next_end = (((bucket + 2) as f64 * size) as usize + 1).min(points.len());
+        let next = match next_start < next_end {
+            true => {
+                let count = (next_end - next_start) as f64;
+                let sum = points[next_start..next_end]
+                    .iter()
+                    .fold((0., 0.), |acc, x| (acc.0 + x.0, acc.1 + x.1));
+                (sum.0 / count, sum.1 / count)
+            }
+            false => points[points.len() - 1],
+        };
+
+        // The point forming the largest triangle with the previous point and
+        // the average of the next bucket
+        let a = points[previous];
+        let mut largest = (start, -1.);
+        for (i, b) in points.iter().enumerate().take(end).skip(start) {
+            let area = ((a.0 - next.0) * (b.1 - a.1) - (a.0 - b.0) * (next.1 - a.1)).abs();
+            if area > largest.1 {
+                largest = (i, area);
+            }
+        }
+
+        indices.push(largest.0);
+        previous = largest.0;
+    }
+
+    indices.push(points.len() - 1);
+    indices
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lttb_keeps_short_lines() {
+        let points = [(0., 0.), (1., 1.), (2., 0.)];
+
+        assert_eq!(lttb(&points, 5), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn lttb_keeps_ends_and_peaks() {
+        let mut points = (0..10).map(|x| (f64::from(x), 0.)).collect::<Vec<_>>();
+        points[5].1 = 10.;
+
+        let indices = lttb(&points, 4);
+        assert_eq!(indices.len(), 4);
+        assert_eq!(indices.first(), Some(&0));
+        assert_eq!(indices.last(), Some(&9));
+        assert!(indices.contains(&5));
+    }
+
+    #[test]
+    fn lttb_is_ordered() {
+        let points = (0..1000)
+            .map(|x| (f64::from(x), f64::from((x * 37) % 101)))
+            .collect::<Vec<_>>();
+
+        let indices = lttb(&points, 100);
+        assert_eq!(indices.len(), 100);
+        assert!(indices.windows(2).all(|x| x[0] < x[1]));
+    }
+}
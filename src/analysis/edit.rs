@@ -0,0 +1,267 @@
+use super::aggregate;
+use crate::models::{Activity, ActivityType, Curve, Duration, Lap, Record, Session};
+use std::ops::Range;
+use uom::si::{
+    f64::{Length, Mass},
+    length::meter,
+};
+
+/// Index of the first sample recorded at or after the elapsed time.
+pub fn sample_at(record: &Record, elapsed: Duration) -> usize {
+    record.duration.iter().take_while(|x| **x < elapsed).count()
+}
+
+/// Trims the activity to the samples in the range. Laps cut by the range
+/// are computed again from their remaining samples, the session is left to
+/// the analysis.
+pub fn crop(activity: &mut Activity, range: Range<usize>, weight: Option<Mass>) {
+    let laps = std::mem::take(&mut activity.lap);
+    activity.lap = laps_within(&activity.record, laps, &range, weight);
+    activity.record = slice(&activity.record, range);
+    activity.session = session(
+        &activity.session.activity_type,
+        &activity.record,
+        &activity.lap,
+    );
+}
+
+/// Splits the activity before the sample `at`, and returns the second part.
+/// It gets the gear of the activity, and an id from its start time.
+pub fn split(activity: &mut Activity, at: usize, weight: Option<Mass>) -> Activity {
+    let length = activity.record.duration.len();
+    let mut second = Activity {
+        id: String::new(),
+        gear_id: activity.gear_id.clone(),
+        session: activity.session.clone(),
+        record: activity.record.clone(),
+        lap: activity.lap.clone(),
+        curve: Curve::default(),
+        efforts: Vec::new(),
+        notes: None,
+        title: None,
+    };
+
+    crop(&mut second, at..length, weight);
+    second.id = second.session.start_time.0.format("%Y%m%d%H%M").to_string();
+    crop(activity, 0..at, weight);
+
+    second
+}
+
+/// Appends the activity `next`, which starts after the activity ended. The
+/// laps of both are kept as they are.
+pub fn merge(activity: &mut Activity, next: Activity) {
+    let record = &mut activity.record;
+    let (length, next_length) = (record.duration.len(), next.record.duration.len());
+
+    let offset = match (record.timestamp.first(), next.record.timestamp.first()) {
+        (Some(x), Some(y)) => Duration::between(y, x),
+        _ => Duration::default(),
+    };
+    let distance = record
+        .distance
+        .iter()
+        .rev()
+        .flatten()
+        .next()
+        .copied()
+        .unwrap_or_else(|| Length::new::<meter>(0.));
+
+    extend(
+        &mut record.cadence,
+        length,
+        next.record.cadence,
+        next_length,
+    );
+    extend(
+        &mut record.distance,
+        length,
+        next.record
+            .distance
+            .iter()
+            .map(|x| x.map(|y| y + distance))
+            .collect(),
+        next_length,
+    );
+    extend(
+        &mut record.altitude,
+        length,
+        next.record.altitude,
+        next_length,
+    );
+    extend(&mut record.speed, length, next.record.speed, next_length);
+    extend(
+        &mut record.heartrate,
+        length,
+        next.record.heartrate,
+        next_length,
+    );
+    extend(&mut record.power, length, next.record.power, next_length);
+    extend(&mut record.lat, length, next.record.lat, next_length);
+    extend(&mut record.lon, length, next.record.lon, next_length);
+    extend(
+        &mut record.temperature,
+        length,
+        next.record.temperature,
+        next_length,
+    );
+    record.timestamp.extend(next.record.timestamp);
+    record
+        .duration
+        .extend(next.record.duration.iter().map(|x| *x + offset));
+
+    activity.lap.extend(next.lap);
+    activity.session = session(
+        &activity.session.activity_type,
+        &activity.record,
+        &activity.lap,
+    );
+}
+
+/// Appends values, filling in what is missing from either of them.
+fn extend<T: Clone>(
+    values: &mut Vec<Option<T>>,
+    length: usize,
+    next: Vec<Option<T>>,
+    next_length: usize,
+) {
+    values.resize(length, None);
+    values.extend(next);
+    values.resize(length + next_length, None);
+}
+
+/// Samples of the record in the range, with the elapsed time and the
+/// distance counted from its start.
+fn slice(record: &Record, range: Range<usize>) -> Record {
+    let duration = record
+        .duration
+        .get(range.start)
+        .copied()
+        .unwrap_or_default();
+    let distance = part(&record.distance, &range).into_iter().flatten().next();
+
+    Record {
+        cadence: part(&record.cadence, &range),
+        distance: part(&record.distance, &range)
+            .into_iter()
+            .map(|x| x.zip(distance).map(|(x, y)| x - y))
+            .collect(),
+        altitude: part(&record.altitude, &range),
+        speed: part(&record.speed, &range),
+        heartrate: part(&record.heartrate, &range),
+        power: part(&record.power, &range),
+        lat: part(&record.lat, &range),
+        lon: part(&record.lon, &range),
+        timestamp: part(&record.timestamp, &range),
+        duration: part(&record.duration, &range)
+            .into_iter()
+            .map(|x| x - duration)
+            .collect(),
+        temperature: part(&record.temperature, &range),
+    }
+}
+
+fn part<T: Clone>(values: &[T], range: &Range<usize>) -> Vec<T> {
+    values
+        .get(range.clone())
+        .map(<[T]>::to_vec)
+        .unwrap_or_default()
+}
+
+/// Samples of every lap. Laps are consecutive, so they are found by elapsed
+/// time, and the last one lasts until the end of the record.
+fn lap_ranges(record: &Record, laps: &[Lap]) -> Vec<Range<usize>> {
+    let mut start = 0;
+    let mut elapsed = 0.;
+
+    laps.iter()
+        .enumerate()
+        .map(|(i, lap)| {
+            elapsed += lap.duration.as_secs_f64();
+            let end = match i + 1 == laps.len() {
+                true => record.duration.len(),
+                false => {
+                    start
+                        + record.duration[start..]
+                            .iter()
+                            .take_while(|x| x.as_secs_f64() < elapsed)
+                            .count()
+                }
+            };
+            let range = start..end;
+            start = end;
+
+            range
+        })
+        .collect()
+}
+
+/// Laps within the range of samples. Laps cut by the range are computed
+/// again from their remaining samples, the others are kept as recorded.
+fn laps_within(
+    record: &Record,
+    laps: Vec<Lap>,
+    range: &Range<usize>,
+    weight: Option<Mass>,
+) -> Vec<Lap> {
+    let ranges = lap_ranges(record, &laps);
+
+    laps.into_iter()
+        .zip(ranges)
+        .filter_map(|(lap, x)| {
+            let (start, end) = (x.start.max(range.start), x.end.min(range.end));
+            if start >= end {
+                return None;
+            }
+
+            match start == x.start && end == x.end {
+                true => Some(lap),
+                false => Some(lap_from(&slice(record, start..end), weight)),
+            }
+        })
+        .collect()
+}
+
+fn lap_from(record: &Record, weight: Option<Mass>) -> Lap {
+    let mut session = Session::default();
+    aggregate::fill(&mut session, record, weight);
+
+    let mut points = record
+        .lat
+        .iter()
+        .zip(record.lon.iter())
+        .filter_map(|x| Some(((*x.0)?, (*x.1)?)));
+    let start = points.next();
+    let end = points.next_back().or(start);
+
+    Lap {
+        cadence_avg: session.cadence_avg,
+        cadence_max: session.cadence_max,
+        heartrate_avg: session.heartrate_avg,
+        heartrate_max: session.heartrate_max,
+        speed_avg: session.speed_avg,
+        speed_max: session.speed_max,
+        power_avg: session.power_avg,
+        power_max: session.power_max,
+        lat_start: start.map(|x| x.0),
+        lon_start: start.map(|x| x.1),
+        lat_end: end.map(|x| x.0),
+        lon_end: end.map(|x| x.1),
+        ascent: session.ascent,
+        descent: session.descent,
+        calories: session.calories,
+        distance: session.distance,
+        duration: session.duration,
+        duration_active: session.duration_active,
+    }
+}
+
+/// Session of an edited record, which the analysis fills in.
+fn session(activity_type: &ActivityType, record: &Record, laps: &[Lap]) -> Session {
+    Session {
+        activity_type: activity_type.clone(),
+        laps: Some(laps.len() as u16),
+        start_time: record.timestamp.first().cloned().unwrap_or_default(),
+        ..Session::default()
+    }
+}
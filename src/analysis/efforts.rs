@@ -0,0 +1,174 @@
+use super::curve;
+use crate::models::{
+    BestEffort, Duration, Effort, EffortValue, PersonalRecord, Record, Session, TimeStamp,
+};
+use uom::si::length::meter;
+
+pub fn best_efforts(record: &Record) -> Vec<BestEffort> {
+    let samples: Vec<(f64, f64)> = record
+        .distance
+        .iter()
+        .zip(record.duration.iter())
+        .filter_map(|(x, y)| x.map(|x| (x.get::<meter>(), y.as_secs_f64())))
+        .collect();
+
+    Effort::ALL
+        .iter()
+        .filter_map(|effort| {
+            let value = if let Some(distance) = effort.distance() {
+                fastest_time(&samples, distance)
+                    .map(|x| EffortValue::Time(Duration::from_secs_f64(x)))
+            } else {
+                effort
+                    .duration()
+                    .and_then(|x| curve::best_power(record, x))
+                    .map(EffortValue::Power)
+            };
+
+            value.map(|value| BestEffort {
+                effort: *effort,
+                value,
+            })
+        })
+        .collect()
+}
+
+/// Finds the shortest time spent covering the distance, using a sliding
+/// window over (distance, time) samples.
+fn fastest_time(samples: &[(f64, f64)], distance: f64) -> Option<f64> {
+    let mut fastest: Option<f64> = None;
+    let mut start = 0;
+
+    for end in 0..samples.len() {
+        while start + 1 < end && samples[end].0 - samples[start + 1].0 >= distance {
+            start += 1;
+        }
+
+        let covered = samples[end].0 - samples[start].0;
+        if covered >= distance {
+            // Scale down to the exact distance, as samples rarely align with it
+            let time = (samples[end].1 - samples[start].1) * distance / covered;
+            fastest = Some(fastest.map_or(time, |x| x.min(time)));
+        }
+    }
+
+    fastest
+}
+
+/// Builds the personal record history from activities in chronological order.
+/// Only running and cycling activities are eligible.
+pub fn progression(
+    activities: impl Iterator<Item = (String, Session, Vec<BestEffort>)>,
+) -> Vec<PersonalRecord> {
+    let mut records: Vec<PersonalRecord> = Vec::new();
+
+    for (id, session, efforts) in activities {
+        merge(&mut records, &id, &session, &efforts);
+    }
+
+    records
+}
+
+/// Adds the efforts of an activity to a personal record history, as if it
+/// had been built with the activity. The history must not hold records of
+/// the activity already.
+pub fn merge(
+    records: &mut Vec<PersonalRecord>,
+    id: &str,
+    session: &Session,
+    efforts: &[BestEffort],
+) {
+    if !session.activity_type.is_running() && !session.activity_type.is_cycling() {
+        return;
+    }
+
+    for effort in efforts {
+        let same = |x: &PersonalRecord| {
+            x.effort == effort.effort && x.activity_type == session.activity_type
+        };
+        let position = records
+            .iter()
+            .position(|x| x.start_time.0 > session.start_time.0)
+            .unwrap_or(records.len());
+
+        let previous = records[..position].iter().rposition(same);
+        if let Some(x) = previous {
+            if !effort.value.is_better_than(&records[x].value) {
+                continue;
+            }
+        }
+
+        // Later records which aren't better than this effort are no records anymore
+        let mut later = records.split_off(position);
+        later.retain(|x| !same(x) || x.value.is_better_than(&effort.value));
+        let current = !later.iter().any(same);
+        if let (Some(x), true) = (previous, current) {
+            records[x].current = false;
+        }
+
+        records.push(PersonalRecord {
+            activity_type: session.activity_type.clone(),
+            effort: effort.effort,
+            value: effort.value,
+            activity_id: id.to_owned(),
+            start_time: TimeStamp(session.start_time.0),
+            current,
+        });
+        records.append(&mut later);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ActivityType;
+    use chrono::{Local, TimeZone};
+
+    /// Rides on consecutive days, with their 5 minute power.
+    fn rides(power: &[u16]) -> Vec<(String, Session, Vec<BestEffort>)> {
+        power
+            .iter()
+            .enumerate()
+            .map(|(i, x)| {
+                let session = Session {
+                    activity_type: ActivityType::Cycling,
+                    start_time: TimeStamp(Local.timestamp(i as i64 * 86400, 0)),
+                    ..Default::default()
+                };
+                let effort = BestEffort {
+                    effort: Effort::Power5Min,
+                    value: EffortValue::Power(*x),
+                };
+                (i.to_string(), session, vec![effort])
+            })
+            .collect()
+    }
+
+    fn ids(records: &[PersonalRecord]) -> Vec<(&str, bool)> {
+        records
+            .iter()
+            .map(|x| (x.activity_id.as_str(), x.current))
+            .collect()
+    }
+
+    #[test]
+    fn progression_keeps_improvements() {
+        let records = progression(rides(&[200, 180, 220, 210, 250]).into_iter());
+
+        assert_eq!(ids(&records), vec![("0", false), ("2", false), ("4", true)]);
+    }
+
+    #[test]
+    fn merge_out_of_order_matches_progression() {
+        let rides = rides(&[200, 180, 220, 210, 250, 230]);
+        let expected = progression(rides.clone().into_iter());
+
+        let mut records = Vec::new();
+        for i in &[5, 3, 1, 4, 0, 2] {
+            let (id, session, efforts) = &rides[*i];
+            merge(&mut records, id, session, efforts);
+        }
+
+        assert_eq!(ids(&records), ids(&expected));
+    }
+}
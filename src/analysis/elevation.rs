@@ -0,0 +1,224 @@
+use crate::models::{Record, Session};
+use std::{
+    collections::{HashMap, VecDeque},
+    convert::TryInto,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+use uom::si::{f64::Length, length::meter};
+
+// Amount of samples in the centered moving average
+const SMOOTHING_WINDOW: usize = 9;
+// Change in altitude required before it counts as ascent or descent
+const HYSTERESIS: f64 = 5.;
+// Amount of DEM tiles kept in memory
+const CACHED_TILES: usize = 4;
+
+/// Ascent and descent as recorded by the device, recomputed from the altitude
+/// samples, and recomputed from a digital elevation model if one is configured.
+pub struct Elevation {
+    pub recomputed: Option<(Length, Length)>,
+    pub dem: Option<(Length, Length)>,
+}
+
+impl Elevation {
+    /// The values from the elevation model are taken from the session, as
+    /// reading its tiles is left to the analysis.
+    pub fn new(record: &Record, session: &Session) -> Self {
+        let altitude = record
+            .altitude
+            .iter()
+            .map(|x| x.map(|y| y.get::<meter>()))
+            .collect::<Vec<Option<f64>>>();
+
+        Self {
+            recomputed: ascent_descent(&smooth(&altitude)),
+            dem: session.dem_elevation,
+        }
+    }
+}
+
+/// Ascent and descent along the track from the altitudes of the digital
+/// elevation model, or None if it covers no part of the track.
+pub fn dem_ascent_descent(record: &Record, dem: &Dem) -> Option<(Length, Length)> {
+    let altitude = record
+        .lat
+        .iter()
+        .zip(record.lon.iter())
+        .map(|x| match x {
+            (Some(lat), Some(lon)) => dem.altitude(*lat, *lon),
+            _ => None,
+        })
+        .collect::<Vec<Option<f64>>>();
+
+    ascent_descent(&smooth(&altitude))
+}
+
+/// Centered moving average, skipping missing samples.
+pub fn smooth(altitude: &[Option<f64>]) -> Vec<Option<f64>> {
+    let half = SMOOTHING_WINDOW / 2;
+
+    (0..altitude.len())
+        .map(|i| {
+            altitude[i]?;
+
+            let window = &altitude[i.saturating_sub(half)..(i + half + 1).min(altitude.len())];
+            let (sum, count) = window
+                .iter()
+                .flatten()
+                .fold((0., 0), |acc, x| (acc.0 + x, acc.1 + 1));
+
+            Some(sum / count as f64)
+        })
+        .collect()
+}
+
+/// Sums up changes in altitude larger than the hysteresis threshold,
+/// so that noise around a level does not add up.
+pub fn ascent_descent(altitude: &[Option<f64>]) -> Option<(Length, Length)> {
+    let mut samples = altitude.iter().flatten();
+    let mut reference = *samples.next()?;
+    let (mut ascent, mut descent) = (0., 0.);
+
+    for x in samples {
+        if x - reference >= HYSTERESIS {
+            ascent += x - reference;
+            reference = *x;
+        } else if reference - x >= HYSTERESIS {
+            descent += reference - x;
+            reference = *x;
+        }
+    }
+
+    Some((Length::new::<meter>(ascent), Length::new::<meter>(descent)))
+}
+
+/// Digital elevation model backed by SRTM .hgt tiles in a directory,
+/// named by their south west corner, e.g. N60E010.hgt.
+#[derive(Clone)]
+pub struct Dem {
+    directory: PathBuf,
+    tiles: Arc<Mutex<TileCache>>,
+}
+
+/// Tiles by their south west corner, with None for tiles missing on disk,
+/// and their corners in the order they were read.
+#[derive(Default)]
+struct TileCache {
+    tiles: HashMap<(i32, i32), Option<Arc<Tile>>>,
+    order: VecDeque<(i32, i32)>,
+}
+
+struct Tile {
+    size: usize,
+    samples: Vec<i16>,
+}
+
+impl Dem {
+    pub fn new(directory: PathBuf) -> Self {
+        Self {
+            directory,
+            tiles: Default::default(),
+        }
+    }
+
+    fn tile(&self, lat: i32, lon: i32) -> Option<Arc<Tile>> {
+        if let Some(x) = self.tiles.lock().unwrap().tiles.get(&(lat, lon)) {
+            return x.clone();
+        }
+
+        let name = format!(
+            "{}{:02}{}{:03}.hgt",
+            if lat >= 0 { 'N' } else { 'S' },
+            lat.abs(),
+            if lon >= 0 { 'E' } else { 'W' },
+            lon.abs()
+        );
+
+        let tile = std::fs::read(self.directory.join(name)).ok().and_then(|x| {
+            let size = ((x.len() / 2) as f64).sqrt() as usize;
+            if size * size * 2 != x.len() || size < 2 {
+                return None;
+            }
+
+            Some(Arc::new(Tile {
+                size,
+                samples: x
+                    .chunks_exact(2)
+                    .map(|y| i16::from_be_bytes(y.try_into().unwrap()))
+                    .collect(),
+            }))
+        });
+
+        // The oldest tile makes room, read while the cache was unlocked
+        let mut cache = self.tiles.lock().unwrap();
+        if cache.tiles.insert((lat, lon), tile.clone()).is_none() {
+            cache.order.push_back((lat, lon));
+        }
+        while cache.order.len() > CACHED_TILES {
+            if let Some(x) = cache.order.pop_front() {
+                cache.tiles.remove(&x);
+            }
+        }
+
+        tile
+    }
+
+    /// Bilinearly interpolated altitude in meters
+    pub fn altitude(&self, lat: f64, lon: f64) -> Option<f64> {
+        let tile = self.tile(lat.floor() as i32, lon.floor() as i32)?;
+        let last = (tile.size - 1) as f64;
+
+        // Rows start at the northern edge of the tile
+        let y = (1. - lat.rem_euclid(1.)) * last;
+        let x = lon.rem_euclid(1.) * last;
+        let (row, col) = (
+            (y.floor() as usize).min(tile.size - 2),
+            (x.floor() as usize).min(tile.size - 2),
+        );
+        let (dy, dx) = (y - row as f64, x - col as f64);
+
+        let sample = |r: usize, c: usize| match tile.samples[r * tile.size + c] {
+            // Voids in the data
+            -32768 => None,
+            x => Some(f64::from(x)),
+        };
+
+        let top = sample(row, col)? * (1. - dx) + sample(row, col + 1)? * dx;
+        let bottom = sample(row + 1, col)? * (1. - dx) + sample(row + 1, col + 1)? * dx;
+
+        Some(top * (1. - dy) + bottom * dy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn meters(x: Option<(Length, Length)>) -> (f64, f64) {
+        let (ascent, descent) = x.unwrap();
+        (ascent.get::<meter>(), descent.get::<meter>())
+    }
+
+    #[test]
+    fn ascent_descent_sums_changes() {
+        let altitude = [Some(0.), Some(10.), None, Some(20.), Some(10.)];
+
+        assert_eq!(meters(ascent_descent(&altitude)), (20., 10.));
+    }
+
+    #[test]
+    fn ascent_descent_ignores_noise() {
+        let altitude = [0., 3., 0., 4., 1., 4.].iter().map(|x| Some(*x));
+
+        assert_eq!(
+            meters(ascent_descent(&altitude.collect::<Vec<_>>())),
+            (0., 0.)
+        );
+    }
+
+    #[test]
+    fn ascent_descent_without_altitude() {
+        assert!(ascent_descent(&[None, None]).is_none());
+    }
+}
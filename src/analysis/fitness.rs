@@ -0,0 +1,118 @@
+use super::{aggregate::MOVING_SPEED, curve::resample};
+use crate::models::Record;
+use chrono::NaiveDate;
+use uom::si::velocity::meter_per_second;
+
+// Time constants in days for the chronic and acute training load
+const CTL_DAYS: f64 = 42.;
+const ATL_DAYS: f64 = 7.;
+
+/// The user settings training load is calculated from.
+pub struct Thresholds {
+    pub heartrate: Option<(u8, u8)>,
+    pub ftp: Option<u16>,
+}
+
+/// Training stress score when power and FTP are available, otherwise
+/// Banister's heart rate based TRIMP.
+pub fn training_load(record: &Record, thresholds: &Thresholds) -> Option<f64> {
+    let ftp = thresholds.ftp.filter(|x| *x > 0);
+
+    if let (Some(ftp), true) = (ftp, record.power.iter().any(Option::is_some)) {
+        // Only seconds spent moving count, so pauses and stops neither add
+        // hours nor lower the normalized power
+        let speed = resample(&record.duration, &record.speed);
+        let has_speed = speed.iter().any(Option::is_some);
+        let power = resample(&record.duration, &record.power)
+            .into_iter()
+            .zip(speed)
+            .filter(|(_, x)| {
+                !has_speed || x.is_some_and(|x| x.get::<meter_per_second>() >= MOVING_SPEED)
+            })
+            .filter_map(|(x, _)| x.map(f64::from))
+            .collect::<Vec<f64>>();
+
+        // Normalized power, the fourth root of the mean of the fourth powers
+        // of the 30 second rolling average
+        let rolling = power
+            .windows(30)
+            .map(|x| (x.iter().sum::<f64>() / 30.).powi(4))
+            .collect::<Vec<f64>>();
+        if rolling.is_empty() {
+            return None;
+        }
+        let normalized = (rolling.iter().sum::<f64>() / rolling.len() as f64).powf(0.25);
+
+        let intensity = normalized / f64::from(ftp);
+        let hours = power.len() as f64 / 3600.;
+
+        Some(hours * intensity * intensity * 100.)
+    } else if let Some((rest, max)) = thresholds.heartrate.filter(|(x, y)| y > x) {
+        let reserve = f64::from(max - rest);
+
+        let trimp = record
+            .duration
+            .windows(2)
+            .zip(record.heartrate.iter())
+            .filter_map(|(d, h)| h.map(|h| (d[1].as_secs_f64() - d[0].as_secs_f64(), h)))
+            // Skip pauses, in line with the heart rate zones
+            .filter(|(d, _)| *d < 30.)
+            .map(|(d, h)| {
+                let ratio = ((f64::from(h) - f64::from(rest)) / reserve).clamp(0., 1.);
+                d / 60. * ratio * 0.64 * (1.92 * ratio).exp()
+            })
+            .sum::<f64>();
+
+        Some(trimp)
+    } else {
+        None
+    }
+}
+
+pub struct FitnessDay {
+    pub date: NaiveDate,
+    pub load: f64,
+    pub fitness: f64,
+    pub fatigue: f64,
+    pub form: f64,
+}
+
+/// Builds the chronic training load (fitness), acute training load (fatigue)
+/// and training stress balance (form) for every day from the first
+/// activity until today.
+pub fn fitness(mut loads: Vec<(NaiveDate, f64)>, today: NaiveDate) -> Vec<FitnessDay> {
+    loads.sort_by_key(|(x, _)| *x);
+
+    let mut days = Vec::new();
+    let mut date = match loads.first() {
+        Some((x, _)) => *x,
+        None => return days,
+    };
+
+    let (mut fitness, mut fatigue) = (0., 0.);
+    let mut loads = loads.into_iter().peekable();
+
+    while date <= today {
+        let mut load = 0.;
+        while let Some((_, x)) = loads.next_if(|(x, _)| *x == date) {
+            load += x;
+        }
+
+        // Form is based on the values before today's training
+        let form = fitness - fatigue;
+        fitness += (load - fitness) / CTL_DAYS;
+        fatigue += (load - fatigue) / ATL_DAYS;
+
+        days.push(FitnessDay {
+            date,
+            load,
+            fitness,
+            fatigue,
+            form,
+        });
+
+        date = date.succ();
+    }
+
+    days
+}
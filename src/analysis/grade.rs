@@ -0,0 +1,149 @@
+use super::elevation;
+use crate::models::{Lap, Record};
+use uom::si::{f64::Velocity, length::meter, velocity::meter_per_second};
+
+// Horizontal distance over which the gradient is measured, in meters
+const GRADIENT_DISTANCE: f64 = 20.;
+// Range of gradients the energy cost model is valid for
+const MAX_GRADIENT: f64 = 0.45;
+
+/// Gradient and grade-adjusted pace of a run, which is the pace on flat
+/// ground requiring the same effort.
+pub struct GradeAdjusted {
+    pub gradient: Vec<Option<f64>>,
+    pub speed: Vec<Option<Velocity>>,
+    pub session: Option<Velocity>,
+    pub laps: Vec<Option<Velocity>>,
+}
+
+impl GradeAdjusted {
+    pub fn new(record: &Record, session_speed: Option<Velocity>, laps: &[Lap]) -> Self {
+        let gradient = gradient(record);
+        let factor: Vec<Option<f64>> = gradient.iter().map(|x| x.map(relative_cost)).collect();
+
+        let speed = (0..record.duration.len())
+            .map(|i| {
+                let speed = match record.speed[i] {
+                    Some(x) => x.get::<meter_per_second>(),
+                    None if i > 0 => match (record.distance[i], record.distance[i - 1]) {
+                        (Some(x), Some(y)) => {
+                            let time = (record.duration[i] - record.duration[i - 1]).as_secs_f64();
+                            (x - y).get::<meter>() / time.max(1.)
+                        }
+                        _ => return None,
+                    },
+                    None => return None,
+                };
+
+                factor[i].map(|x| Velocity::new::<meter_per_second>(speed * x))
+            })
+            .collect();
+
+        let session = session_speed
+            .zip(adjustment(record, &factor, 0, record.duration.len()))
+            .map(|(x, y)| x * y);
+
+        // Laps are consecutive, so their samples are found by elapsed time
+        let mut start = 0;
+        let mut elapsed = 0.;
+        let laps = laps
+            .iter()
+            .map(|lap| {
+                elapsed += lap.duration.as_secs_f64();
+                let end = start
+                    + record.duration[start..]
+                        .iter()
+                        .take_while(|x| x.as_secs_f64() < elapsed)
+                        .count();
+                let adjustment = adjustment(record, &factor, start, end);
+                start = end;
+
+                lap.speed_avg.zip(adjustment).map(|(x, y)| x * y)
+            })
+            .collect();
+
+        Self {
+            gradient,
+            speed,
+            session,
+            laps,
+        }
+    }
+}
+
+/// Gradient at every sample, from the smoothed altitude over the last
+/// `GRADIENT_DISTANCE` meters.
+pub fn gradient(record: &Record) -> Vec<Option<f64>> {
+    let altitude: Vec<Option<f64>> = record
+        .altitude
+        .iter()
+        .map(|x| x.map(|y| y.get::<meter>()))
+        .collect();
+    let altitude = elevation::smooth(&altitude);
+
+    let points: Vec<Option<(f64, f64)>> = record
+        .distance
+        .iter()
+        .zip(altitude.iter())
+        .map(|x| match x {
+            (Some(distance), Some(altitude)) => Some((distance.get::<meter>(), *altitude)),
+            _ => None,
+        })
+        .collect();
+
+    let mut start = 0;
+    points
+        .iter()
+        .map(|point| {
+            let (distance, altitude) = (*point)?;
+
+            while let Some(x) = points[start..].iter().flatten().next() {
+                if distance - x.0 <= GRADIENT_DISTANCE {
+                    break;
+                }
+                start += 1;
+            }
+
+            let previous = points[..start].iter().rev().flatten().next()?;
+            let gradient = (altitude - previous.1) / (distance - previous.0);
+
+            Some(gradient.clamp(-MAX_GRADIENT, MAX_GRADIENT))
+        })
+        .collect()
+}
+
+/// Energy cost of running at a gradient relative to flat ground,
+/// after Minetti et al.
+fn relative_cost(gradient: f64) -> f64 {
+    let i = gradient;
+    let cost =
+        155.4 * i.powi(5) - 30.4 * i.powi(4) - 43.3 * i.powi(3) + 46.3 * i.powi(2) + 19.5 * i + 3.6;
+
+    cost / 3.6
+}
+
+/// Ratio between the flat equivalent distance and the distance covered
+/// between the samples `start` and `end`.
+fn adjustment(record: &Record, factor: &[Option<f64>], start: usize, end: usize) -> Option<f64> {
+    let (mut distance, mut adjusted) = (0., 0.);
+    let mut previous: Option<f64> = None;
+
+    for (current, factor) in record.distance[start..end].iter().zip(&factor[start..end]) {
+        let current = match current {
+            Some(x) => x.get::<meter>(),
+            None => continue,
+        };
+
+        if let Some(x) = previous {
+            let increment = current - x;
+            distance += increment;
+            adjusted += increment * factor.unwrap_or(1.);
+        }
+        previous = Some(current);
+    }
+
+    match distance > 0. {
+        true => Some(adjusted / distance),
+        false => None,
+    }
+}
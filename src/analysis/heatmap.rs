@@ -0,0 +1,185 @@
+use crate::models::Record;
+use std::{f64::consts::PI, hash::Hasher};
+
+pub const TILE_SIZE: usize = 256;
+pub const MAX_ZOOM: u8 = 18;
+// Amount of activities through a pixel at which the color saturates
+const SATURATION: f64 = 20.;
+
+/// FNV-1a hash. Cache directories are named by it and thumbnails store it,
+/// so unlike the hashes of `DefaultHasher` it has to stay the same across
+/// builds.
+pub struct Fnv(u64);
+
+impl Default for Fnv {
+    fn default() -> Self {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for Fnv {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for x in bytes {
+            self.0 = (self.0 ^ u64::from(*x)).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+/// A web mercator map tile.
+#[derive(Clone, Copy)]
+pub struct Tile {
+    pub z: u8,
+    pub x: u32,
+    pub y: u32,
+}
+
+impl Tile {
+    pub fn new(z: u8, x: u32, y: u32) -> Option<Self> {
+        match z <= MAX_ZOOM && x < 1 << z && y < 1 << z {
+            true => Some(Self { z, x, y }),
+            false => None,
+        }
+    }
+
+    /// Bounds of the tile as (north, east, south, west), in degrees.
+    pub fn bounds(&self) -> (f64, f64, f64, f64) {
+        let n = f64::from(1u32 << self.z);
+        let lon = |x: f64| x / n * 360. - 180.;
+        let lat = |y: f64| (PI * (1. - 2. * y / n)).sinh().atan().to_degrees();
+
+        (
+            lat(f64::from(self.y)),
+            lon(f64::from(self.x + 1)),
+            lat(f64::from(self.y + 1)),
+            lon(f64::from(self.x)),
+        )
+    }
+
+    /// Position of a coordinate in pixels, relative to the top left of the tile.
+    fn project(&self, (lat, lon): (f64, f64)) -> (f64, f64) {
+        let scale = (TILE_SIZE as f64) * f64::from(1u32 << self.z);
+        let lat = lat.to_radians();
+
+        (
+            (lon + 180.) / 360. * scale - f64::from(self.x) * TILE_SIZE as f64,
+            (1. - (lat.tan() + 1. / lat.cos()).ln() / PI) / 2. * scale
+                - f64::from(self.y) * TILE_SIZE as f64,
+        )
+    }
+}
+
+/// Counts the tracks passing through every pixel of a tile. Every track
+/// counts once per pixel, however often it passes.
+pub fn density<'a>(tile: Tile, records: impl Iterator<Item = &'a Record>) -> Vec<u32> {
+    let mut counts = vec![0u32; TILE_SIZE * TILE_SIZE];
+    // Index of the last track which marked a pixel
+    let mut marked = vec![0usize; TILE_SIZE * TILE_SIZE];
+
+    for (i, record) in records.enumerate() {
+        let track = record
+            .lat
+            .iter()
+            .zip(record.lon.iter())
+            .filter_map(|x| match x {
+                (Some(lat), Some(lon)) => Some(tile.project((*lat, *lon))),
+                _ => None,
+            })
+            .collect::<Vec<(f64, f64)>>();
+
+        let mut mark = |x: usize, y: usize| {
+            let pixel = y * TILE_SIZE + x;
+            if marked[pixel] != i + 1 {
+                marked[pixel] = i + 1;
+                counts[pixel] += 1;
+            }
+        };
+
+        for x in track.windows(2) {
+            if let Some((a, b)) = clip(x[0], x[1]) {
+                draw_line(a, b, &mut mark);
+            }
+        }
+    }
+
+    counts
+}
+
+/// Clips a line to the tile, or returns None if it lies outside of it.
+fn clip(a: (f64, f64), b: (f64, f64)) -> Option<((f64, f64), (f64, f64))> {
+    let max = TILE_SIZE as f64 - 1e-9;
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let (mut t0, mut t1) = (0f64, 1f64);
+
+    for &(p, q) in &[(-dx, a.0), (dx, max - a.0), (-dy, a.1), (dy, max - a.1)] {
+        if p == 0. {
+            if q < 0. {
+                return None;
+            }
+        } else {
+            let t = q / p;
+            if p < 0. {
+                t0 = t0.max(t);
+            } else {
+                t1 = t1.min(t);
+            }
+        }
+    }
+
+    match t0 <= t1 {
+        true => Some((
+            (a.0 + t0 * dx, a.1 + t0 * dy),
+            (a.0 + t1 * dx, a.1 + t1 * dy),
+        )),
+        false => None,
+    }
+}
+
+/// Marks the pixels along a line, with one step per pixel.
+pub fn draw_line(a: (f64, f64), b: (f64, f64), mark: &mut impl FnMut(usize, usize)) {
+    let steps = (b.0 - a.0).abs().max((b.1 - a.1).abs()).ceil().max(1.);
+
+    for step in 0..=steps as usize {
+        let t = step as f64 / steps;
+        let x = a.0 + (b.0 - a.0) * t;
+        let y = a.1 + (b.1 - a.1) * t;
+        mark(x as usize, y as usize);
+    }
+}
+
+/// Colors the counts from transparent through red and yellow to white, on
+/// a logarithmic scale, and encodes them as a PNG image.
+pub fn render(counts: &[u32]) -> Vec<u8> {
+    let mut pixels = Vec::with_capacity(counts.len() * 4);
+
+    for count in counts {
+        if *count == 0 {
+            pixels.extend_from_slice(&[0, 0, 0, 0]);
+            continue;
+        }
+
+        let value = ((1. + f64::from(*count)).ln() / (1. + SATURATION).ln()).min(1.);
+        let channel = |x: f64| (x.clamp(0., 1.) * 255.).round() as u8;
+        pixels.extend_from_slice(&[
+            channel(0.6 + value * 1.2),
+            channel(value * 2. - 0.4),
+            channel(value * 3. - 2.),
+            channel(0.5 + value),
+        ]);
+    }
+
+    let mut image = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut image, TILE_SIZE as u32, TILE_SIZE as u32);
+        encoder.set_color(png::ColorType::RGBA);
+        encoder.set_depth(png::BitDepth::Eight);
+        // Writing to a vector does not fail
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(&pixels).unwrap();
+    }
+
+    image
+}
@@ -0,0 +1,30 @@
+pub mod aggregate;
+pub mod climbs;
+pub mod curve;
+pub mod downsample;
+pub mod edit;
+pub mod efforts;
+pub mod elevation;
+pub mod fitness;
+pub mod grade;
+pub mod heatmap;
+pub mod photo;
+pub mod routes;
+pub mod segments;
+pub mod splits;
+pub mod thumbnail;
+pub mod track;
+
+use crate::models::Activity;
+use elevation::Dem;
+use uom::si::f64::Mass;
+
+/// Computes the values derived from the record of an activity,
+/// which has to be done whenever the record changes.
+pub fn analyze(activity: &mut Activity, weight: Option<Mass>, dem: Option<&Dem>) {
+    aggregate::fill(&mut activity.session, &activity.record, weight);
+    activity.session.dem_elevation =
+        dem.and_then(|x| elevation::dem_ascent_descent(&activity.record, x));
+    activity.curve = curve::mean_maximal(&activity.record);
+    activity.efforts = efforts::best_efforts(&activity.record);
+}
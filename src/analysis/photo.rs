@@ -0,0 +1,158 @@
+use crate::models::{Photo, Record, TimeStamp};
+use chrono::{FixedOffset, Local, NaiveDate, TimeZone};
+use exif::{Exif, In, Tag, Value};
+use image::{codecs::jpeg::JpegEncoder, DynamicImage, GenericImageView, ImageFormat};
+
+const THUMBNAIL_SIZE: u32 = 320;
+const THUMBNAIL_QUALITY: u8 = 80;
+// Most pixels an image can have, so small files of huge images aren't
+// decoded into gigabytes
+const MAX_PIXELS: u64 = 64_000_000;
+// Longest time a photo can be taken before the start or after the end of an
+// activity and still be pinned to its track, in seconds
+const MAX_PHOTO_OFFSET: i64 = 10 * 60;
+
+/// Reads the EXIF data of a JPEG or PNG image, and renders its thumbnail.
+/// Returns None if the image is of another format, larger than 64 megapixels
+/// or can't be decoded.
+pub fn read(image: &[u8]) -> Option<(Photo, Vec<u8>)> {
+    let content_type = match image::guess_format(image).ok()? {
+        ImageFormat::Jpeg => "image/jpeg",
+        ImageFormat::Png => "image/png",
+        _ => return None,
+    };
+    let exif = exif::Reader::new()
+        .read_from_container(&mut std::io::Cursor::new(image))
+        .ok();
+
+    let photo = Photo {
+        content_type: content_type.to_string(),
+        taken: exif.as_ref().and_then(taken),
+        position: exif.as_ref().and_then(position),
+    };
+    let orientation = exif
+        .as_ref()
+        .and_then(|x| x.get_field(Tag::Orientation, In::PRIMARY))
+        .and_then(|x| x.value.get_uint(0))
+        .unwrap_or(1);
+
+    Some((photo, thumbnail(image, orientation)?))
+}
+
+/// Where the photo is pinned on the track: the position it was taken at if
+/// it tells, or else the track point recorded closest to the time it was
+/// taken.
+pub fn pin(photo: &Photo, record: &Record) -> Option<(f64, f64)> {
+    if photo.position.is_some() {
+        return photo.position;
+    }
+
+    let taken = photo.taken.as_ref()?.0;
+    let (start, end) = (record.timestamp.first()?.0, record.timestamp.last()?.0);
+    if taken.signed_duration_since(start).num_seconds() < -MAX_PHOTO_OFFSET
+        || taken.signed_duration_since(end).num_seconds() > MAX_PHOTO_OFFSET
+    {
+        return None;
+    }
+
+    record
+        .timestamp
+        .iter()
+        .zip(record.lat.iter().zip(record.lon.iter()))
+        .filter_map(|(time, x)| Some((time, ((*x.0)?, (*x.1)?))))
+        .min_by_key(|(time, _)| taken.signed_duration_since(time.0).num_seconds().abs())
+        .map(|(_, x)| x)
+}
+
+/// Time the photo was taken, in the time zone it tells or else in local time.
+fn taken(exif: &Exif) -> Option<TimeStamp> {
+    let mut time = match &exif.get_field(Tag::DateTimeOriginal, In::PRIMARY)?.value {
+        Value::Ascii(x) => exif::DateTime::from_ascii(x.first()?).ok()?,
+        _ => return None,
+    };
+    // An offset which can't be read leaves the time in local time
+    if let Some(Value::Ascii(x)) = exif
+        .get_field(Tag::OffsetTimeOriginal, In::PRIMARY)
+        .map(|x| &x.value)
+    {
+        if let Some(offset) = x.first() {
+            time.parse_offset(offset).ok();
+        }
+    }
+
+    let naive = NaiveDate::from_ymd_opt(time.year.into(), time.month.into(), time.day.into())?
+        .and_hms_opt(time.hour.into(), time.minute.into(), time.second.into())?;
+    let local = match time.offset {
+        Some(x) => FixedOffset::east_opt(i32::from(x) * 60)?
+            .from_local_datetime(&naive)
+            .single()?
+            .with_timezone(&Local),
+        None => Local.from_local_datetime(&naive).earliest()?,
+    };
+
+    Some(TimeStamp(local))
+}
+
+/// Position the photo was taken at, as (lat, lon).
+fn position(exif: &Exif) -> Option<(f64, f64)> {
+    let lat = coordinate(exif, Tag::GPSLatitude, Tag::GPSLatitudeRef, b'S')?;
+    let lon = coordinate(exif, Tag::GPSLongitude, Tag::GPSLongitudeRef, b'W')?;
+
+    match lat.abs() <= 90. && lon.abs() <= 180. {
+        true => Some((lat, lon)),
+        false => None,
+    }
+}
+
+/// Coordinate in degrees, from degrees, minutes and seconds and the
+/// hemisphere it is in.
+fn coordinate(exif: &Exif, tag: Tag, reference: Tag, negative: u8) -> Option<f64> {
+    let degrees = match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Rational(x) if x.len() == 3 => {
+            x[0].to_f64() + x[1].to_f64() / 60. + x[2].to_f64() / 3600.
+        }
+        _ => return None,
+    };
+    let sign = match exif.get_field(reference, In::PRIMARY).map(|x| &x.value) {
+        Some(Value::Ascii(x)) if x.first()?.first() == Some(&negative) => -1.,
+        _ => 1.,
+    };
+
+    Some(degrees * sign).filter(|x| x.is_finite())
+}
+
+/// Renders a JPEG thumbnail, turned the way the camera was held.
+fn thumbnail(image: &[u8], orientation: u32) -> Option<Vec<u8>> {
+    let reader = || {
+        image::io::Reader::new(std::io::Cursor::new(image))
+            .with_guessed_format()
+            .ok()
+    };
+    let (width, height) = reader()?.into_dimensions().ok()?;
+    if u64::from(width) * u64::from(height) > MAX_PIXELS {
+        return None;
+    }
+    let image = reader()?.decode().ok()?;
+    // Small images are kept as they are
+    let image = match image.width() > THUMBNAIL_SIZE || image.height() > THUMBNAIL_SIZE {
+        true => image.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE),
+        false => image,
+    };
+    let image = match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image,
+    };
+
+    let mut thumbnail = Vec::new();
+    JpegEncoder::new_with_quality(&mut thumbnail, THUMBNAIL_QUALITY)
+        .encode_image(&DynamicImage::ImageRgb8(image.to_rgb8()))
+        .ok()?;
+
+    Some(thumbnail)
+}
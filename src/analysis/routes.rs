@@ -0,0 +1,128 @@
+use super::track::haversine;
+use crate::models::Record;
+
+// Spacing of the points of a simplified track, in meters
+const MIN_SPACING: f64 = 50.;
+// Upper bound on the amount of points of a simplified track
+const MAX_POINTS: f64 = 200.;
+// Fréchet distance below which two tracks follow the same course, in meters
+const MATCH_DISTANCE: f64 = 100.;
+// Relative difference in length above which two tracks can't match
+const MAX_LENGTH_DIFFERENCE: f64 = 0.1;
+
+/// Resamples the track to points at equal distances along it, which keeps
+/// the comparison of long tracks cheap. Returns the points and the length.
+pub fn simplify(record: &Record) -> Option<(Vec<(f64, f64)>, f64)> {
+    let points: Vec<(f64, f64)> = record
+        .lat
+        .iter()
+        .zip(record.lon.iter())
+        .filter_map(|x| match x {
+            (Some(lat), Some(lon)) => Some((*lat, *lon)),
+            _ => None,
+        })
+        .collect();
+
+    if points.len() < 2 {
+        return None;
+    }
+
+    let length: f64 = points.windows(2).map(|x| haversine(x[0], x[1])).sum();
+    let spacing = MIN_SPACING.max(length / MAX_POINTS);
+
+    let mut simplified = vec![points[0]];
+    let mut travelled = 0.;
+    for x in points.windows(2) {
+        travelled += haversine(x[0], x[1]);
+        if travelled >= spacing {
+            simplified.push(x[1]);
+            travelled = 0.;
+        }
+    }
+    simplified.push(points[points.len() - 1]);
+
+    Some((simplified, length))
+}
+
+/// Whether two simplified tracks follow the same course in the same direction.
+pub fn matches(a: &[(f64, f64)], a_length: f64, b: &[(f64, f64)], b_length: f64) -> bool {
+    if (a_length - b_length).abs() > a_length.max(b_length) * MAX_LENGTH_DIFFERENCE {
+        return false;
+    }
+
+    // Discretization adds up to half the spacing to the distance
+    let spacing = MIN_SPACING.max(a_length.max(b_length) / MAX_POINTS);
+    frechet_within(a, b, MATCH_DISTANCE.max(spacing))
+}
+
+/// Whether the discrete Fréchet distance between the tracks is within the
+/// threshold. Stops as soon as a whole row of the coupling exceeds it.
+fn frechet_within(a: &[(f64, f64)], b: &[(f64, f64)], threshold: f64) -> bool {
+    if a.is_empty() || b.is_empty() {
+        return false;
+    }
+
+    let mut previous: Vec<f64> = Vec::with_capacity(b.len());
+    for (j, point) in b.iter().enumerate() {
+        let distance = haversine(a[0], *point);
+        previous.push(match j {
+            0 => distance,
+            _ => distance.max(previous[j - 1]),
+        });
+    }
+
+    for x in &a[1..] {
+        let mut current: Vec<f64> = Vec::with_capacity(b.len());
+
+        for (j, point) in b.iter().enumerate() {
+            let reachable = match j {
+                0 => previous[0],
+                _ => previous[j].min(previous[j - 1]).min(current[j - 1]),
+            };
+            current.push(haversine(*x, *point).max(reachable));
+        }
+
+        if current.iter().all(|x| *x > threshold) {
+            return false;
+        }
+        previous = current;
+    }
+
+    previous[b.len() - 1] <= threshold
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(lat: f64, points: usize) -> Vec<(f64, f64)> {
+        (0..points).map(|x| (lat, x as f64 * 0.001)).collect()
+    }
+
+    #[test]
+    fn frechet_within_same_track() {
+        assert!(frechet_within(&line(0., 10), &line(0., 10), 1.));
+    }
+
+    #[test]
+    fn frechet_within_offset_track() {
+        // 0.001 degrees of latitude are about 111 meters
+        let (a, b) = (line(0., 10), line(0.001, 10));
+
+        assert!(frechet_within(&a, &b, 150.));
+        assert!(!frechet_within(&a, &b, 50.));
+    }
+
+    #[test]
+    fn frechet_within_reversed_track() {
+        let a = line(0., 10);
+        let b = a.iter().rev().copied().collect::<Vec<(f64, f64)>>();
+
+        assert!(!frechet_within(&a, &b, 150.));
+    }
+
+    #[test]
+    fn frechet_within_empty_track() {
+        assert!(!frechet_within(&[], &line(0., 10), 150.));
+    }
+}
@@ -0,0 +1,160 @@
+use super::track::haversine;
+use crate::models::{Activity, Record, Segment, SegmentEffort};
+use uom::si::{f64::Length, length::meter};
+
+// Distance within which the track passes a point of the segment, in meters
+const MATCH_RADIUS: f64 = 30.;
+// Minimum distance between the stored points of a segment, in meters
+const POINT_SPACING: f64 = 20.;
+// Track distance relative to the segment after which a pass is given up
+const MAX_DETOUR: f64 = 1.5;
+
+/// Creates a segment from the stretch of the activity between two distances.
+pub fn segment(
+    name: &str,
+    username: &str,
+    activity: &Activity,
+    start: Length,
+    end: Length,
+) -> Option<Segment> {
+    let record = &activity.record;
+    let mut points: Vec<(f64, f64)> = Vec::new();
+    let (mut first, mut last): (Option<f64>, Option<f64>) = (None, None);
+
+    for i in 0..record.distance.len() {
+        let (distance, point) = match (record.distance[i], record.lat[i], record.lon[i]) {
+            (Some(distance), Some(lat), Some(lon)) => (distance, (lat, lon)),
+            _ => continue,
+        };
+        if distance < start || distance > end {
+            continue;
+        }
+
+        first.get_or_insert(distance.get::<meter>());
+        last = Some(distance.get::<meter>());
+
+        match points.last() {
+            Some(x) if haversine(*x, point) < POINT_SPACING => (),
+            _ => points.push(point),
+        }
+    }
+
+    if points.len() < 2 {
+        return None;
+    }
+
+    Some(Segment {
+        name: name.to_string(),
+        username: username.to_string(),
+        activity_type: activity.session.activity_type.clone(),
+        points,
+        distance: Length::new::<meter>(last? - first?),
+    })
+}
+
+/// Finds the fastest pass through the segment in the record. A pass has to
+/// start near the first point of the segment and pass near all other points
+/// in order, which also rules out passes in the opposite direction.
+pub fn find_effort(segment: &Segment, record: &Record) -> Option<SegmentEffort> {
+    let track: Vec<(usize, (f64, f64))> = record
+        .lat
+        .iter()
+        .zip(record.lon.iter())
+        .enumerate()
+        .filter_map(|(i, x)| match x {
+            (Some(lat), Some(lon)) => Some((i, (*lat, *lon))),
+            _ => None,
+        })
+        .collect();
+    let first = *segment.points.first()?;
+
+    let mut best: Option<SegmentEffort> = None;
+    let mut k = 0;
+
+    while k < track.len() {
+        if haversine(track[k].1, first) > MATCH_RADIUS {
+            k += 1;
+            continue;
+        }
+
+        // Start at the closest point while passing the start of the segment
+        let mut start = k;
+        while k + 1 < track.len() && haversine(track[k + 1].1, first) <= MATCH_RADIUS {
+            k += 1;
+            if haversine(track[k].1, first) < haversine(track[start].1, first) {
+                start = k;
+            }
+        }
+
+        if let Some(end) = follow(segment, &track, start) {
+            let effort = effort(record, track[start].0, track[end].0);
+            if best.as_ref().is_none_or(|x| effort.duration < x.duration) {
+                best = Some(effort);
+            }
+        }
+
+        k += 1;
+    }
+
+    best
+}
+
+/// Follows the track from `start` along the segment, and returns the index
+/// at which the last point of the segment is passed.
+fn follow(segment: &Segment, track: &[(usize, (f64, f64))], start: usize) -> Option<usize> {
+    let last = *segment.points.last()?;
+    let limit = segment.distance.get::<meter>() * MAX_DETOUR + 2. * MATCH_RADIUS;
+    let mut next = 1;
+    let mut travelled = 0.;
+
+    for j in start..track.len() {
+        if j > start {
+            travelled += haversine(track[j - 1].1, track[j].1);
+        }
+        if travelled > limit {
+            return None;
+        }
+
+        while next < segment.points.len()
+            && haversine(track[j].1, segment.points[next]) <= MATCH_RADIUS
+        {
+            next += 1;
+        }
+
+        if next == segment.points.len() {
+            // End at the closest point while passing the end of the segment
+            let end = (j..track.len())
+                .take_while(|x| haversine(track[*x].1, last) <= MATCH_RADIUS)
+                .fold(j, |acc, x| {
+                    if haversine(track[x].1, last) < haversine(track[acc].1, last) {
+                        x
+                    } else {
+                        acc
+                    }
+                });
+            return Some(end);
+        }
+    }
+
+    None
+}
+
+fn effort(record: &Record, start: usize, end: usize) -> SegmentEffort {
+    fn average<T: Copy + Into<f64>>(values: &[Option<T>]) -> Option<f64> {
+        let (sum, count) = values
+            .iter()
+            .flatten()
+            .fold((0., 0), |acc, x| (acc.0 + (*x).into(), acc.1 + 1));
+        match count {
+            0 => None,
+            _ => Some(sum / f64::from(count)),
+        }
+    }
+
+    SegmentEffort {
+        start_time: record.timestamp[start].clone(),
+        duration: record.duration[end] - record.duration[start],
+        power_avg: average(&record.power[start..=end]).map(|x| x.round() as u16),
+        heartrate_avg: average(&record.heartrate[start..=end]).map(|x| x.round() as u8),
+    }
+}
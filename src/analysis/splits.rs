@@ -0,0 +1,197 @@
+use crate::models::{Duration, Record, Unit};
+use uom::si::{
+    f64::{Length, Velocity},
+    length::{kilometer, meter, mile},
+    velocity::meter_per_second,
+};
+
+pub struct Split {
+    pub distance: Length,
+    pub duration: Duration,
+    pub speed: Velocity,
+    pub heartrate_avg: Option<u8>,
+    pub altitude_change: Option<Length>,
+    pub power_avg: Option<u16>,
+    pub cadence_avg: Option<u8>,
+}
+
+/// Splits the record at every kilometer or mile, depending on the unit.
+/// The remainder after the last whole split is included as a shorter split.
+pub fn splits(record: &Record, unit: &Unit) -> Vec<Split> {
+    let length = match unit {
+        Unit::Metric => Length::new::<kilometer>(1.),
+        Unit::Imperial => Length::new::<mile>(1.),
+    }
+    .get::<meter>();
+
+    let mut splits = Vec::new();
+    let (first, last) = match (
+        record.distance.iter().flatten().next(),
+        record.distance.iter().rposition(Option::is_some),
+    ) {
+        (Some(x), Some(y)) => (x.get::<meter>(), y),
+        _ => return splits,
+    };
+    // Sample the split starts at, with the elapsed seconds it starts at
+    let mut start: Option<(usize, f64)> = None;
+    // Records may start counting the distance at more than zero
+    let mut start_distance = first;
+    // Distance and elapsed seconds of the sample before
+    let mut previous: Option<(f64, f64)> = None;
+
+    for (i, distance) in record.distance.iter().enumerate() {
+        let distance = match distance {
+            Some(x) => x.get::<meter>(),
+            None => continue,
+        };
+        let seconds = record.duration[i].as_secs_f64();
+        let first = start.get_or_insert((i, seconds));
+
+        // Splits end exactly at every boundary, at a time interpolated
+        // between the samples around it, so their lengths don't drift
+        while distance - start_distance >= length {
+            let boundary = start_distance + length;
+            let end_time = match previous {
+                Some((x, time)) if distance > x => {
+                    time + (seconds - time) * ((boundary - x) / (distance - x)).clamp(0., 1.)
+                }
+                _ => seconds,
+            };
+
+            splits.push(split(record, first.0, i, length, end_time - first.1));
+            *first = (i, end_time);
+            start_distance = boundary;
+        }
+
+        if i == last && distance > start_distance {
+            let duration = seconds - first.1;
+            splits.push(split(
+                record,
+                first.0,
+                i,
+                distance - start_distance,
+                duration,
+            ));
+        }
+        previous = Some((distance, seconds));
+    }
+
+    splits
+}
+
+/// Split over the samples from `start` to `end`, which covered the distance
+/// in meters within the duration in seconds.
+fn split(record: &Record, start: usize, end: usize, distance: f64, duration: f64) -> Split {
+    fn average<T: Copy + Into<f64>>(values: &[Option<T>]) -> Option<f64> {
+        let (sum, count) = values
+            .iter()
+            .flatten()
+            .fold((0., 0), |acc, x| (acc.0 + (*x).into(), acc.1 + 1));
+        if count > 0 {
+            Some(sum / count as f64)
+        } else {
+            None
+        }
+    }
+
+    let duration = Duration::from_secs_f64(duration.max(0.));
+    let altitude_change = match (record.altitude[start], record.altitude[end]) {
+        (Some(x), Some(y)) => Some(y - x),
+        _ => None,
+    };
+
+    let speed = if duration.as_secs_f64() > 0. {
+        distance / duration.as_secs_f64()
+    } else {
+        0.
+    };
+
+    Split {
+        distance: Length::new::<meter>(distance),
+        duration,
+        speed: Velocity::new::<meter_per_second>(speed),
+        heartrate_avg: average(&record.heartrate[start..=end]).map(|x| x.round() as u8),
+        altitude_change,
+        power_avg: average(&record.power[start..=end]).map(|x| x.round() as u16),
+        cadence_avg: average(&record.cadence[start..=end]).map(|x| x.round() as u8),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Record moving at 10 m/s, with a sample every `step` seconds.
+    fn record(seconds: usize, step: usize) -> Record {
+        let samples = (0..=seconds).step_by(step).collect::<Vec<usize>>();
+        let n = samples.len();
+
+        Record {
+            distance: samples
+                .iter()
+                .map(|x| Some(Length::new::<meter>(*x as f64 * 10.)))
+                .collect(),
+            duration: samples
+                .iter()
+                .map(|x| Duration::from_secs_f64(*x as f64))
+                .collect(),
+            altitude: vec![None; n],
+            heartrate: vec![None; n],
+            power: vec![None; n],
+            cadence: vec![None; n],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn splits_with_remainder() {
+        let splits = splits(&record(250, 1), &Unit::Metric);
+
+        let distances = splits
+            .iter()
+            .map(|x| x.distance.get::<meter>().round())
+            .collect::<Vec<f64>>();
+        assert_eq!(distances, vec![1000., 1000., 500.]);
+        let durations = splits
+            .iter()
+            .map(|x| x.duration.as_secs_f64().round())
+            .collect::<Vec<f64>>();
+        assert_eq!(durations, vec![100., 100., 50.]);
+    }
+
+    #[test]
+    fn splits_end_at_boundaries_between_samples() {
+        // Samples are 30 m apart, so no sample falls on a boundary
+        let splits = splits(&record(300, 3), &Unit::Metric);
+
+        assert_eq!(splits.len(), 3);
+        for x in &splits {
+            assert!((x.distance.get::<meter>() - 1000.).abs() < 1e-6);
+            assert!((x.duration.as_secs_f64() - 100.).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn splits_from_first_distance() {
+        // Some devices count the distance from before the recording starts
+        let mut record = record(250, 1);
+        for x in record.distance.iter_mut().flatten() {
+            *x += Length::new::<meter>(400.);
+        }
+        let splits = splits(&record, &Unit::Metric);
+
+        let distances = splits
+            .iter()
+            .map(|x| x.distance.get::<meter>().round())
+            .collect::<Vec<f64>>();
+        assert_eq!(distances, vec![1000., 1000., 500.]);
+    }
+
+    #[test]
+    fn splits_without_distance() {
+        let mut record = record(100, 1);
+        record.distance = vec![None; record.distance.len()];
+
+        assert!(splits(&record, &Unit::Metric).is_empty());
+    }
+}
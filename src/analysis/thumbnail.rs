@@ -0,0 +1,424 @@
+use super::heatmap::{draw_line, Fnv};
+use crate::models::Record;
+use image::imageops::{self, FilterType};
+use std::{
+    f64::consts::PI,
+    hash::{Hash, Hasher},
+};
+use uom::si::{length::meter, velocity::meter_per_second};
+
+const WIDTH: usize = 200;
+const HEIGHT: usize = 200;
+const SPARKLINE_HEIGHT: usize = 100;
+const PADDING: f64 = 10.;
+const BACKGROUND: [u8; 3] = [247, 248, 249];
+const ROUTE: [u8; 3] = [255, 0, 0];
+// Colors the track is colored with, as on the map of the activity
+const SCALE_STEPS: usize = 16;
+const TILE_SIZE: u32 = 256;
+// Short tracks are drawn at this zoom level at most, like on the map of the activity
+const MAX_TILE_ZOOM: u8 = 17;
+
+/// Channel drawn as a sparkline for activities without a track, in the
+/// order they are preferred.
+#[derive(Hash, Clone, Copy)]
+enum Channel {
+    Altitude,
+    Heartrate,
+    Power,
+}
+
+impl Channel {
+    fn values(self, record: &Record) -> Vec<Option<f64>> {
+        match self {
+            Channel::Altitude => record
+                .altitude
+                .iter()
+                .map(|x| x.map(|x| x.get::<meter>()))
+                .collect(),
+            Channel::Heartrate => record.heartrate.iter().map(|x| x.map(f64::from)).collect(),
+            Channel::Power => record.power.iter().map(|x| x.map(f64::from)).collect(),
+        }
+    }
+
+    fn color(self) -> [u8; 3] {
+        match self {
+            Channel::Altitude => [87, 85, 217],
+            Channel::Heartrate => [232, 86, 0],
+            Channel::Power => [50, 182, 67],
+        }
+    }
+}
+
+/// What the thumbnail of an activity shows.
+enum Content {
+    /// Points of the track with their colors
+    Track(Vec<(f64, f64)>, Vec<[u8; 3]>),
+    Sparkline(Channel, Vec<Option<f64>>),
+}
+
+impl Content {
+    fn new(record: &Record) -> Option<Self> {
+        let (samples, track): (Vec<usize>, Vec<(f64, f64)>) = record
+            .lat
+            .iter()
+            .zip(record.lon.iter())
+            .enumerate()
+            .filter_map(|(i, x)| match x {
+                (Some(lat), Some(lon)) => Some((i, (*lat, *lon))),
+                _ => None,
+            })
+            .unzip();
+
+        if track.len() > 1 {
+            return Some(Content::Track(track, track_colors(record, &samples)));
+        }
+
+        [Channel::Altitude, Channel::Heartrate, Channel::Power]
+            .iter()
+            .map(|x| (*x, x.values(record)))
+            .find(|(_, x)| x.iter().flatten().nth(1).is_some())
+            .map(|(channel, values)| Content::Sparkline(channel, values))
+    }
+}
+
+/// Hash of the data the thumbnail is drawn from, or None if there is
+/// nothing to draw. Tells whether a thumbnail is outdated.
+pub fn hash(record: &Record) -> Option<u64> {
+    let mut hasher = Fnv::default();
+
+    match Content::new(record)? {
+        Content::Track(track, colors) => {
+            for (lat, lon) in track {
+                lat.to_bits().hash(&mut hasher);
+                lon.to_bits().hash(&mut hasher);
+            }
+            colors.hash(&mut hasher);
+        }
+        Content::Sparkline(channel, values) => {
+            channel.hash(&mut hasher);
+            for x in values {
+                x.map(f64::to_bits).hash(&mut hasher);
+            }
+        }
+    }
+
+    Some(hasher.finish())
+}
+
+/// Colors of the points of the track, by the first of speed, heart rate and
+/// power which was recorded. The scale leaves out the highest and lowest
+/// values like the map of the activity does, and points without a value are
+/// drawn in the plain color.
+fn track_colors(record: &Record, samples: &[usize]) -> Vec<[u8; 3]> {
+    let speed = record
+        .speed
+        .iter()
+        .map(|x| x.map(|x| x.get::<meter_per_second>()))
+        .collect::<Vec<Option<f64>>>();
+    let channels = [
+        speed,
+        Channel::Heartrate.values(record),
+        Channel::Power.values(record),
+    ];
+    let values = match channels
+        .iter()
+        .map(|x| {
+            samples
+                .iter()
+                .map(|i| x.get(*i).copied().flatten())
+                .collect::<Vec<Option<f64>>>()
+        })
+        .find(|x| x.iter().any(Option::is_some))
+    {
+        Some(x) => x,
+        None => return vec![ROUTE; samples.len()],
+    };
+
+    let mut sorted = values.iter().flatten().copied().collect::<Vec<f64>>();
+    sorted.sort_by(f64::total_cmp);
+    let min = sorted[(sorted.len() as f64 * 0.02).floor() as usize];
+    let max = sorted[(sorted.len() as f64 * 0.98).ceil() as usize - 1];
+    let range = match max - min > 0. {
+        true => max - min,
+        false => 1.,
+    };
+
+    values
+        .iter()
+        .map(|x| match x {
+            Some(x) => {
+                let step = (((x - min) / range).clamp(0., 1.) * (SCALE_STEPS - 1) as f64).round();
+                scale_color(step / (SCALE_STEPS - 1) as f64)
+            }
+            None => ROUTE,
+        })
+        .collect()
+}
+
+/// Color at the fraction of the scale, from blue for low values over green
+/// to red for high ones. Same as hsl(240 * (1 - x), 90%, 45%) on the map.
+fn scale_color(x: f64) -> [u8; 3] {
+    let (hue, saturation, lightness) = (240. * (1. - x), 0.9, 0.45);
+    let a = saturation * f64::min(lightness, 1. - lightness);
+    let channel = |n: f64| {
+        let k = (n + hue / 30.) % 12.;
+        let value = lightness - a * (k - 3.).min(9. - k).clamp(-1., 1.);
+        (value * 255.).round() as u8
+    };
+
+    [channel(0.), channel(8.), channel(4.)]
+}
+
+/// Draws the track on a plain background, or a sparkline of the first of
+/// altitude, heart rate, and power which was recorded if there is no track.
+/// Needs no map tiles, and returns a PNG image.
+pub fn render(record: &Record) -> Option<Vec<u8>> {
+    match Content::new(record)? {
+        Content::Track(track, colors) => Some(track_image(&track, &colors)),
+        Content::Sparkline(channel, values) => Some(sparkline_image(channel, &values)),
+    }
+}
+
+/// Draws the track on map tiles, which `tile` gets by zoom level, x and y.
+/// Returns a PNG image, or None if there is no track or a tile is missing.
+pub fn render_on_tiles(
+    record: &Record,
+    mut tile: impl FnMut(u8, u32, u32) -> Option<Vec<u8>>,
+) -> Option<Vec<u8>> {
+    let (track, colors) = match Content::new(record)? {
+        Content::Track(track, colors) => (track, colors),
+        Content::Sparkline(..) => return None,
+    };
+
+    let points = mercator(&track);
+    let (min_x, max_x, min_y, max_y) = extent(&points);
+    // The closest zoom level the whole track fits in
+    let zoom = (0..=MAX_TILE_ZOOM)
+        .rev()
+        .find(|x| {
+            let scale = f64::from(TILE_SIZE << x);
+            (max_x - min_x) * scale <= WIDTH as f64 - 2. * PADDING
+                && (max_y - min_y) * scale <= HEIGHT as f64 - 2. * PADDING
+        })
+        .unwrap_or_default();
+    let scale = f64::from(TILE_SIZE << zoom);
+    // Pixel of the map at the top left corner of the image
+    let origin = (
+        (min_x + max_x) / 2. * scale - WIDTH as f64 / 2.,
+        (min_y + max_y) / 2. * scale - HEIGHT as f64 / 2.,
+    );
+
+    let mut canvas = Canvas::new(WIDTH, HEIGHT);
+    let size = f64::from(TILE_SIZE);
+    let tiles = 1i64 << zoom;
+    let range = |start: f64, length: usize| {
+        (start / size).floor() as i64..=((start + length as f64) / size).floor() as i64
+    };
+
+    for y in range(origin.1, HEIGHT).filter(|x| (0..tiles).contains(x)) {
+        for x in range(origin.0, WIDTH) {
+            // Tiles repeat around the antimeridian
+            let image = image::load_from_memory(&tile(zoom, x.rem_euclid(tiles) as u32, y as u32)?)
+                .ok()?
+                .to_rgb8();
+            // High resolution tiles are scaled to the size of the others
+            let image = match image.dimensions() == (TILE_SIZE, TILE_SIZE) {
+                true => image,
+                false => imageops::resize(&image, TILE_SIZE, TILE_SIZE, FilterType::Triangle),
+            };
+
+            let left = x as f64 * size - origin.0;
+            let top = y as f64 * size - origin.1;
+            for (column, row, pixel) in image.enumerate_pixels() {
+                let (column, row) = (left + f64::from(column), top + f64::from(row));
+                if column >= 0. && row >= 0. {
+                    canvas.set(column as usize, row as usize, pixel.0);
+                }
+            }
+        }
+    }
+
+    let pixels = points
+        .iter()
+        .map(|(x, y)| (x * scale - origin.0, y * scale - origin.1))
+        .collect::<Vec<(f64, f64)>>();
+    draw_track(&mut canvas, &pixels, &colors);
+
+    Some(canvas.encode())
+}
+
+/// Web mercator coordinates of the points, from 0 to 1 across the map, so
+/// the shape matches the map of the activity.
+fn mercator(track: &[(f64, f64)]) -> Vec<(f64, f64)> {
+    track
+        .iter()
+        .map(|(lat, lon)| {
+            let lat = lat.to_radians();
+            (
+                (lon + 180.) / 360.,
+                (1. - (PI / 4. + lat / 2.).tan().ln() / PI) / 2.,
+            )
+        })
+        .collect()
+}
+
+/// Bounds of the points as (min x, max x, min y, max y).
+fn extent(points: &[(f64, f64)]) -> (f64, f64, f64, f64) {
+    points
+        .iter()
+        .fold((f64::MAX, f64::MIN, f64::MAX, f64::MIN), |acc, (x, y)| {
+            (acc.0.min(*x), acc.1.max(*x), acc.2.min(*y), acc.3.max(*y))
+        })
+}
+
+/// Draws the track in the colors of the points its segments start at.
+fn draw_track(canvas: &mut Canvas, pixels: &[(f64, f64)], colors: &[[u8; 3]]) {
+    for (x, color) in pixels.windows(2).zip(colors.iter()) {
+        draw_line(x[0], x[1], &mut |x, y| canvas.dot(x, y, *color));
+    }
+}
+
+fn track_image(track: &[(f64, f64)], colors: &[[u8; 3]]) -> Vec<u8> {
+    let mut canvas = Canvas::new(WIDTH, HEIGHT);
+
+    let points = mercator(track);
+    let (min_x, max_x, min_y, max_y) = extent(&points);
+    let scale = ((WIDTH as f64 - 2. * PADDING) / (max_x - min_x))
+        .min((HEIGHT as f64 - 2. * PADDING) / (max_y - min_y));
+    let scale = match scale.is_finite() {
+        true => scale,
+        false => 0.,
+    };
+    // Center the track in the image
+    let offset = (
+        (WIDTH as f64 - (max_x - min_x) * scale) / 2.,
+        (HEIGHT as f64 - (max_y - min_y) * scale) / 2.,
+    );
+
+    let pixels = points
+        .iter()
+        .map(|(x, y)| {
+            (
+                offset.0 + (x - min_x) * scale,
+                offset.1 + (y - min_y) * scale,
+            )
+        })
+        .collect::<Vec<(f64, f64)>>();
+    draw_track(&mut canvas, &pixels, colors);
+
+    canvas.encode()
+}
+
+fn sparkline_image(channel: Channel, values: &[Option<f64>]) -> Vec<u8> {
+    let mut canvas = Canvas::new(WIDTH, SPARKLINE_HEIGHT);
+
+    // Mean of the values falling into every column
+    let columns = (0..WIDTH)
+        .map(|column| {
+            let start = column * values.len() / WIDTH;
+            let end = ((column + 1) * values.len() / WIDTH).max(start + 1);
+            let column = values[start..end.min(values.len())]
+                .iter()
+                .flatten()
+                .collect::<Vec<&f64>>();
+
+            match column.is_empty() {
+                true => None,
+                false => Some(column.iter().copied().sum::<f64>() / column.len() as f64),
+            }
+        })
+        .collect::<Vec<Option<f64>>>();
+
+    let (min, max) = columns
+        .iter()
+        .flatten()
+        .fold((f64::MAX, f64::MIN), |acc, x| {
+            (acc.0.min(*x), acc.1.max(*x))
+        });
+    let range = match max - min > 0. {
+        true => max - min,
+        false => 1.,
+    };
+    let height = SPARKLINE_HEIGHT as f64 - 2. * PADDING;
+    let y = |x: f64| PADDING + (1. - (x - min) / range) * height;
+
+    let color = channel.color();
+    let fill = [
+        ((u16::from(color[0]) + 3 * u16::from(BACKGROUND[0])) / 4) as u8,
+        ((u16::from(color[1]) + 3 * u16::from(BACKGROUND[1])) / 4) as u8,
+        ((u16::from(color[2]) + 3 * u16::from(BACKGROUND[2])) / 4) as u8,
+    ];
+
+    let mut previous: Option<(f64, f64)> = None;
+    for (column, value) in columns.iter().enumerate() {
+        let value = match value {
+            Some(x) => y(*x),
+            None => {
+                previous = None;
+                continue;
+            }
+        };
+
+        for row in value as usize..SPARKLINE_HEIGHT {
+            canvas.set(column, row, fill);
+        }
+        if let Some(a) = previous {
+            draw_line(a, (column as f64, value), &mut |x, y| {
+                canvas.dot(x, y, color)
+            });
+        }
+        previous = Some((column as f64, value));
+    }
+
+    canvas.encode()
+}
+
+/// An RGB image, which ignores drawing outside of it.
+struct Canvas {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Canvas {
+    fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: BACKGROUND.repeat(width * height),
+        }
+    }
+
+    fn set(&mut self, x: usize, y: usize, color: [u8; 3]) {
+        if x < self.width && y < self.height {
+            let i = (y * self.width + x) * 3;
+            self.pixels[i..i + 3].copy_from_slice(&color);
+        }
+    }
+
+    /// Sets the pixel and its neighbours, for lines three pixels wide.
+    fn dot(&mut self, x: usize, y: usize, color: [u8; 3]) {
+        for dy in 0..3 {
+            for dx in 0..3 {
+                if let (Some(x), Some(y)) = ((x + dx).checked_sub(1), (y + dy).checked_sub(1)) {
+                    self.set(x, y, color);
+                }
+            }
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut image = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut image, self.width as u32, self.height as u32);
+            encoder.set_color(png::ColorType::RGB);
+            encoder.set_depth(png::BitDepth::Eight);
+            // Writing to a vector does not fail
+            let mut writer = encoder.write_header().unwrap();
+            writer.write_image_data(&self.pixels).unwrap();
+        }
+
+        image
+    }
+}
@@ -0,0 +1,179 @@
+use crate::models::{Activity, ActivityType, Cleaning, Duration, Record, Session};
+use uom::si::{f64::Length, length::meter, velocity::meter_per_second};
+
+const EARTH_RADIUS: f64 = 6_371_000.;
+// Gaps between samples longer than this, in seconds, are pauses
+const PAUSE_GAP: f64 = 10.;
+// Slower movement than this, in meters per second, counts as standing still
+const STATIONARY_SPEED: f64 = 0.5;
+// Standing still for longer than this, in seconds, is a pause
+const STATIONARY_DURATION: f64 = 10.;
+// Consecutive outliers after which the track is assumed to have moved
+const MAX_OUTLIERS: usize = 10;
+// Amount of points in the centered moving average when smoothing
+const SMOOTHING_WINDOW: usize = 5;
+
+/// Great circle distance in meters between two (lat, lon) points.
+pub fn haversine(a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (lat1, lat2) = (a.0.to_radians(), b.0.to_radians());
+    let (dlat, dlon) = ((b.0 - a.0).to_radians(), (b.1 - a.1).to_radians());
+
+    let h = (dlat / 2.).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.).sin().powi(2);
+    2. * EARTH_RADIUS * h.sqrt().asin()
+}
+
+/// Sets the corner coordinates of the session from the track.
+pub fn set_bounds(session: &mut Session, record: &Record) {
+    if record.lat.iter().all(Option::is_none) {
+        return;
+    }
+
+    let lat = record.lat.iter().flatten().copied();
+    let lon = record.lon.iter().flatten().copied();
+
+    session.nec_lat = Some(lat.clone().fold(f64::NAN, f64::max));
+    session.nec_lon = Some(lon.clone().fold(f64::NAN, f64::max));
+    session.swc_lat = Some(lat.fold(f64::NAN, f64::min));
+    session.swc_lon = Some(lon.fold(f64::NAN, f64::min));
+}
+
+fn max_speed(activity_type: &ActivityType) -> f64 {
+    match activity_type {
+        ActivityType::Running => 12.,
+        ActivityType::Cycling => 30.,
+        ActivityType::Other(_) => 70.,
+    }
+}
+
+/// Removes GPS outliers by implausible speed, trims points recorded while
+/// standing still, and optionally smooths the track. Returns the cleaning
+/// summary and the raw record, which is needed to undo the cleaning.
+pub fn clean(activity: &mut Activity, smooth: bool) -> (Cleaning, Record) {
+    let raw = std::mem::take(&mut activity.record);
+    let mut record = raw.clone();
+
+    let seconds: Vec<f64> = record.duration.iter().map(Duration::as_secs_f64).collect();
+    let point = |record: &Record, i: usize| match (record.lat[i], record.lon[i]) {
+        (Some(lat), Some(lon)) => Some((lat, lon)),
+        _ => None,
+    };
+
+    // Outliers
+    let max_speed = max_speed(&activity.session.activity_type);
+    let (mut outliers, mut rejected) = (0, 0);
+    let mut previous: Option<usize> = None;
+
+    for i in 0..record.lat.len() {
+        let current = match point(&record, i) {
+            Some(x) => x,
+            None => continue,
+        };
+
+        if let Some(j) = previous {
+            let time = (seconds[i] - seconds[j]).max(1.);
+            let speed = haversine(point(&record, j).unwrap(), current) / time;
+
+            if speed > max_speed && rejected < MAX_OUTLIERS {
+                record.lat[i] = None;
+                record.lon[i] = None;
+                outliers += 1;
+                rejected += 1;
+                continue;
+            }
+        }
+
+        previous = Some(i);
+        rejected = 0;
+    }
+
+    // Pauses, either as gaps in the recording or as standing still
+    let mut pauses = Vec::new();
+    let mut stationary: Option<usize> = None;
+
+    for i in 1..seconds.len() {
+        if seconds[i] - seconds[i - 1] > PAUSE_GAP {
+            pauses.push((record.duration[i - 1], record.duration[i]));
+        }
+
+        let speed = match (record.speed[i], record.distance[i], record.distance[i - 1]) {
+            (Some(x), _, _) => x.get::<meter_per_second>(),
+            (None, Some(x), Some(y)) => {
+                (x - y).get::<meter>() / (seconds[i] - seconds[i - 1]).max(1.)
+            }
+            _ => continue,
+        };
+
+        if speed < STATIONARY_SPEED {
+            stationary.get_or_insert(i - 1);
+        } else if let Some(start) = stationary.take() {
+            if seconds[i - 1] - seconds[start] > STATIONARY_DURATION {
+                pauses.push((record.duration[start], record.duration[i - 1]));
+
+                // Keep the end points, so the track stays connected
+                for j in start + 1..i - 1 {
+                    record.lat[j] = None;
+                    record.lon[j] = None;
+                }
+            }
+        }
+    }
+    pauses.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+    if smooth {
+        let valid: Vec<usize> = (0..record.lat.len())
+            .filter(|x| point(&record, *x).is_some())
+            .collect();
+        let points: Vec<(f64, f64)> = valid.iter().map(|x| point(&record, *x).unwrap()).collect();
+        let half = SMOOTHING_WINDOW / 2;
+
+        for (i, index) in valid.iter().enumerate() {
+            let window = &points[i.saturating_sub(half)..(i + half + 1).min(points.len())];
+            let count = window.len() as f64;
+
+            record.lat[*index] = Some(window.iter().map(|x| x.0).sum::<f64>() / count);
+            record.lon[*index] = Some(window.iter().map(|x| x.1).sum::<f64>() / count);
+        }
+    }
+
+    let distance = activity.session.distance;
+
+    // Outliers inflate the distance, so recompute it from the cleaned track
+    if outliers > 0 {
+        let mut total = 0.;
+        let mut previous: Option<(f64, f64)> = None;
+
+        for i in 0..record.distance.len() {
+            if let Some(current) = point(&record, i) {
+                if let Some(x) = previous {
+                    total += haversine(x, current);
+                }
+                previous = Some(current);
+            }
+            if record.distance[i].is_some() || previous.is_some() {
+                record.distance[i] = Some(Length::new::<meter>(total));
+            }
+        }
+
+        activity.session.distance = Some(Length::new::<meter>(total));
+    }
+
+    set_bounds(&mut activity.session, &record);
+    activity.record = record;
+
+    (
+        Cleaning {
+            outliers,
+            pauses,
+            smoothed: smooth,
+            distance,
+        },
+        raw,
+    )
+}
+
+/// Undoes the cleaning of the track, restoring the raw record.
+pub fn restore(activity: &mut Activity, cleaning: Cleaning, raw: Record) {
+    activity.session.distance = cleaning.distance;
+    set_bounds(&mut activity.session, &raw);
+    activity.record = raw;
+}
@@ -1,6 +1,6 @@
 use crate::models::Unit;
 use serde::Deserialize;
-use std::{fs::read, net::Ipv4Addr};
+use std::{fs::read, net::Ipv4Addr, path::PathBuf};
 
 #[derive(Deserialize, Clone)]
 pub struct Config {
@@ -16,6 +16,16 @@ pub struct Config {
     pub port: u16,
     #[serde(default = "default_units")]
     pub units: String,
+    #[serde(default)]
+    pub dem_directory: Option<PathBuf>,
+    #[serde(default = "default_tile_url")]
+    pub tile_url: String,
+    #[serde(default)]
+    pub mbtiles: Option<PathBuf>,
+    #[serde(default = "default_tile_cache_days")]
+    pub tile_cache_days: u64,
+    #[serde(default = "default_thumbnail_tiles")]
+    pub thumbnail_tiles: bool,
 }
 
 impl Default for Config {
@@ -27,6 +37,11 @@ impl Default for Config {
             address: default_address(),
             port: default_port(),
             units: default_units(),
+            dem_directory: None,
+            tile_url: default_tile_url(),
+            mbtiles: None,
+            tile_cache_days: default_tile_cache_days(),
+            thumbnail_tiles: default_thumbnail_tiles(),
         }
     }
 }
@@ -69,6 +84,18 @@ fn default_port() -> u16 {
     8080
 }
 
+fn default_tile_url() -> String {
+    "https://tile.openstreetmap.org/{z}/{x}/{y}.png".into()
+}
+
+fn default_tile_cache_days() -> u64 {
+    30
+}
+
+fn default_thumbnail_tiles() -> bool {
+    true
+}
+
 pub fn config() -> Config {
     if let Ok(bytes) = read("config.toml") {
         let config = String::from_utf8(bytes).expect("Config file is not valid UTF-8.");
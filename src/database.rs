@@ -1,5 +1,10 @@
 pub mod activities;
 pub mod gear;
+pub mod index;
+pub mod jobs;
+pub mod photos;
+pub mod routes;
+pub mod segments;
 pub mod users;
 
 use crate::error::Result;
@@ -9,6 +14,10 @@ pub struct Database {
     pub users: users::UserTree,
     pub activities: activities::ActivityTree,
     pub gear: gear::GearTree,
+    pub segments: segments::SegmentTree,
+    pub routes: routes::RouteTree,
+    pub photos: photos::PhotoTree,
+    pub jobs: jobs::JobTree,
     pub _db: sled::Db,
 }
 
@@ -16,27 +25,73 @@ impl Database {
     pub fn load_or_create() -> Result<Self> {
         let db = sled::open("db")?;
 
-        Ok(Self {
+        let database = Self {
             users: users::UserTree {
                 username_password: db.open_tree("username_password")?,
                 username_standardgear: db.open_tree("username_standardgear")?,
                 username_heartraterest: db.open_tree("username_heartraterest")?,
                 username_heartratemax: db.open_tree("username_heartratemax")?,
+                username_ftp: db.open_tree("username_ftp")?,
+                username_weight: db.open_tree("username_weight")?,
             },
 
             activities: activities::ActivityTree {
                 usernameid_gearid: db.open_tree("usernameid_gearid")?,
                 usernameid_session: db.open_tree("usernameid_session")?,
                 usernameid_record: db.open_tree("usernameid_record")?,
+                usernameid_rawrecord: db.open_tree("usernameid_rawrecord")?,
+                usernameid_cleaning: db.open_tree("usernameid_cleaning")?,
                 usernameid_lap: db.open_tree("usernameid_lap")?,
+                usernameid_curve: db.open_tree("usernameid_curve")?,
+                usernameid_efforts: db.open_tree("usernameid_efforts")?,
+                usernameid_load: db.open_tree("usernameid_load")?,
                 usernameid_notes: db.open_tree("usernameid_notes")?,
+                usernameid_title: db.open_tree("usernameid_title")?,
+                usernameid_thumbnail: db.open_tree("usernameid_thumbnail")?,
+                usernameid_edits: db.open_tree("usernameid_edits")?,
+                usernameid_recordversion: db.open_tree("usernameid_recordversion")?,
+                usernametermid: db.open_tree("usernametermid")?,
+                usernameid_terms: db.open_tree("usernameid_terms")?,
+                username_personalrecords: db.open_tree("username_personalrecords")?,
+                username_activitycount: db.open_tree("username_activitycount")?,
             },
 
             gear: gear::GearTree {
                 usernameid_gear: db.open_tree("usernameid_gear")?,
             },
 
+            segments: segments::SegmentTree {
+                db: db.clone(),
+                segmentid_segment: db.open_tree("segmentid_segment")?,
+                segmentidusernameid_effort: db.open_tree("segmentidusernameid_effort")?,
+            },
+
+            routes: routes::RouteTree {
+                usernameid_route: db.open_tree("usernameid_route")?,
+            },
+
+            photos: photos::PhotoTree {
+                db: db.clone(),
+                usernameidphotoid_photo: db.open_tree("usernameidphotoid_photo")?,
+                usernameidphotoid_image: db.open_tree("usernameidphotoid_image")?,
+                usernameidphotoid_thumbnail: db.open_tree("usernameidphotoid_thumbnail")?,
+            },
+
+            jobs: jobs::JobTree {
+                db: db.clone(),
+                jobid_job: db.open_tree("jobid_job")?,
+                jobid_running: db.open_tree("jobid_running")?,
+                jobid_failed: db.open_tree("jobid_failed")?,
+                job_jobid: db.open_tree("job_jobid")?,
+                running_users: Default::default(),
+            },
+
             _db: db,
-        })
+        };
+        database.activities.index_missing()?;
+        database.activities.count_all()?;
+        database.activities.load_missing(&database.users)?;
+
+        Ok(database)
     }
 }
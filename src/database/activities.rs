@@ -1,18 +1,43 @@
 use crate::{
+    analysis::{efforts, fitness},
     error::{Error, ErrorKind, Result},
-    models::{Activity, Duration, Lap, Record, Session, UserTotals},
+    models::{
+        Activity, BestEffort, Cleaning, Curve, Duration, Edit, Lap, PersonalRecord, Record,
+        Session, UserTotals,
+    },
 };
 use chrono::{self, Datelike, Local};
 use rmp_serde as rmps;
+use std::convert::TryInto;
 use uom::si::{f64::Length, length::meter};
 
+// Longest stored notes and titles, in bytes
+const MAX_NOTES_LENGTH: usize = 20_000;
+const MAX_TITLE_LENGTH: usize = 200;
+
 #[derive(Clone)]
 pub struct ActivityTree {
     pub(super) usernameid_gearid: sled::Tree,
     pub(super) usernameid_session: sled::Tree,
     pub(super) usernameid_record: sled::Tree,
+    /// Counts the writes of every record, which is left when an activity is
+    /// removed, so a record at the same id later gets a new version
+    pub(super) usernameid_recordversion: sled::Tree,
+    pub(super) usernameid_rawrecord: sled::Tree,
+    pub(super) usernameid_cleaning: sled::Tree,
     pub(super) usernameid_lap: sled::Tree,
+    pub(super) usernameid_curve: sled::Tree,
+    pub(super) usernameid_efforts: sled::Tree,
+    pub(super) usernameid_load: sled::Tree,
     pub(super) usernameid_notes: sled::Tree,
+    pub(super) usernameid_title: sled::Tree,
+    pub(super) usernameid_thumbnail: sled::Tree,
+    pub(super) usernameid_edits: sled::Tree,
+    pub(super) usernametermid: sled::Tree,
+    pub(super) usernameid_terms: sled::Tree,
+    pub(super) username_personalrecords: sled::Tree,
+    /// Amount of activities of every user, counted again on every start
+    pub(super) username_activitycount: sled::Tree,
 }
 
 impl ActivityTree {
@@ -23,13 +48,24 @@ impl ActivityTree {
         Ok(self.usernameid_session.contains_key(&key)?)
     }
 
+    /// Stores an uploaded activity. If it overwrites one, what the user set
+    /// is kept, while the raw record and edits of the old track are dropped,
+    /// as they can't be applied to the new one.
     pub fn insert(&self, activity: Activity, username: &str) -> Result<()> {
         if self.exists(username, &activity.id)? {
+            let mut key = username.as_bytes().to_vec();
+            key.push(0xff);
+            key.extend_from_slice(activity.id.as_bytes());
+            self.usernameid_rawrecord.remove(&key)?;
+            self.usernameid_cleaning.remove(&key)?;
+            self.usernameid_edits.remove(&key)?;
+
             let existing = self.get_activity(username, &activity.id)?;
             let activity = Activity {
                 id: existing.id,
                 gear_id: existing.gear_id,
                 notes: existing.notes,
+                title: existing.title,
                 session: Session {
                     activity_type: existing.session.activity_type,
                     ..activity.session
@@ -49,27 +85,405 @@ impl ActivityTree {
         key.extend_from_slice(&activity.id.as_bytes());
 
         let session = rmps::to_vec(&activity.session)?;
-        self.usernameid_session.insert(&key, session)?;
+        if self.usernameid_session.insert(&key, session)?.is_none() {
+            self.add_to_count(username, 1)?;
+        }
 
         let record = rmps::to_vec(&activity.record)?;
         self.usernameid_record.insert(&key, record)?;
+        self.usernameid_recordversion.update_and_fetch(&key, |x| {
+            let version = x
+                .and_then(|x| x.try_into().ok())
+                .map_or(0, u64::from_be_bytes);
+            Some((version + 1).to_be_bytes().to_vec())
+        })?;
 
         let lap = rmps::to_vec(&activity.lap)?;
         self.usernameid_lap.insert(&key, lap)?;
 
+        let curve = rmps::to_vec(&activity.curve)?;
+        self.usernameid_curve.insert(&key, curve)?;
+
+        let efforts = rmps::to_vec(&activity.efforts)?;
+        self.usernameid_efforts.insert(&key, efforts)?;
+
+        let gear_id = rmps::to_vec(&activity.gear_id)?;
+        self.usernameid_gearid.insert(&key, gear_id)?;
+
+        let notes = set_text(
+            &self.usernameid_notes,
+            &key,
+            activity.notes,
+            MAX_NOTES_LENGTH,
+        )?;
+        let title = set_text(
+            &self.usernameid_title,
+            &key,
+            activity.title,
+            MAX_TITLE_LENGTH,
+        )?;
+
+        self.index(
+            username,
+            &activity.id,
+            &activity.session,
+            activity.gear_id.as_deref(),
+            title.as_deref(),
+            notes.as_deref(),
+        )
+    }
+
+    pub fn remove(&self, username: &str, id: &str) -> Result<()> {
+        let mut key = username.as_bytes().to_vec();
+        key.push(0xff);
+        key.extend_from_slice(id.as_bytes());
+
+        if self.usernameid_session.remove(&key)?.is_some() {
+            self.add_to_count(username, -1)?;
+        }
+        self.usernameid_record.remove(&key)?;
+        self.usernameid_rawrecord.remove(&key)?;
+        self.usernameid_cleaning.remove(&key)?;
+        self.usernameid_lap.remove(&key)?;
+        self.usernameid_curve.remove(&key)?;
+        self.usernameid_efforts.remove(&key)?;
+        self.usernameid_load.remove(&key)?;
+        self.usernameid_gearid.remove(&key)?;
+        self.usernameid_notes.remove(&key)?;
+        self.usernameid_title.remove(&key)?;
+        self.usernameid_thumbnail.remove(&key)?;
+        self.usernameid_edits.remove(&key)?;
+
+        self.unindex(username, id)
+    }
+
+    /// Stores the values the analysis derived from the record of an activity.
+    /// The activity type, gear, title and description are kept as they are
+    /// stored, as they may have been changed while it was analyzed.
+    pub fn set_analysis(&self, username: &str, activity: &Activity) -> Result<()> {
+        let mut key = username.as_bytes().to_vec();
+        key.push(0xff);
+        key.extend_from_slice(activity.id.as_bytes());
+
+        let session = self.usernameid_session.update_and_fetch(&key, |x| {
+            let stored = x?;
+            let mut session = activity.session.clone();
+            if let Ok(x) = rmps::from_read_ref::<_, Session>(stored) {
+                session.activity_type = x.activity_type;
+            }
+
+            Some(rmps::to_vec(&session).unwrap_or_else(|_| stored.to_vec()))
+        })?;
+        // Activities deleted while they were analyzed stay deleted
+        let session: Session = match session {
+            Some(x) => rmps::from_read_ref(&x)
+                .map_err(|_| Error::BadServerResponse("Failed to store the analysis"))?,
+            None => return Ok(()),
+        };
+
+        let curve = rmps::to_vec(&activity.curve)?;
+        self.usernameid_curve.insert(&key, curve)?;
+
+        let efforts = rmps::to_vec(&activity.efforts)?;
+        self.usernameid_efforts.insert(&key, efforts)?;
+
+        self.index(
+            username,
+            &activity.id,
+            &session,
+            self.get_gear_id(username, &activity.id)?.as_deref(),
+            self.get_title(username, &activity.id)?.as_deref(),
+            self.get_notes(username, &activity.id)?.as_deref(),
+        )
+    }
+
+    /// Stores what the user set for an activity. The values derived from its
+    /// record are kept as they are stored, as it may be analyzed meanwhile.
+    pub fn set_details(&self, username: &str, activity: &Activity) -> Result<()> {
+        let mut key = username.as_bytes().to_vec();
+        key.push(0xff);
+        key.extend_from_slice(activity.id.as_bytes());
+
+        let activity_type = &activity.session.activity_type;
+        let session = self.usernameid_session.update_and_fetch(&key, |x| {
+            let stored = x?;
+            let mut session = rmps::from_read_ref::<_, Session>(stored).ok()?;
+            session.activity_type = activity_type.clone();
+
+            Some(rmps::to_vec(&session).unwrap_or_else(|_| stored.to_vec()))
+        })?;
+        let session: Session = session
+            .and_then(|x| rmps::from_read_ref(&x).ok())
+            .ok_or(Error::BadRequest(ErrorKind::NotFound, "Session not found"))?;
+
         let gear_id = rmps::to_vec(&activity.gear_id)?;
         self.usernameid_gearid.insert(&key, gear_id)?;
 
-        if let Some(mut x) = activity.notes {
-            x.truncate(300);
-            self.usernameid_notes.insert(&key, x.as_bytes())?;
+        let notes = set_text(
+            &self.usernameid_notes,
+            &key,
+            activity.notes.clone(),
+            MAX_NOTES_LENGTH,
+        )?;
+        let title = set_text(
+            &self.usernameid_title,
+            &key,
+            activity.title.clone(),
+            MAX_TITLE_LENGTH,
+        )?;
+
+        self.index(
+            username,
+            &activity.id,
+            &session,
+            activity.gear_id.as_deref(),
+            title.as_deref(),
+            notes.as_deref(),
+        )
+    }
+
+    /// Stores the edits of an activity, the last one being undone first.
+    pub fn set_edits(&self, username: &str, id: &str, edits: &[Edit]) -> Result<()> {
+        let mut key = username.as_bytes().to_vec();
+        key.push(0xff);
+        key.extend_from_slice(id.as_bytes());
+
+        if edits.is_empty() {
+            self.usernameid_edits.remove(&key)?;
         } else {
-            self.usernameid_notes.remove(&key)?;
+            self.usernameid_edits.insert(&key, rmps::to_vec(edits)?)?;
         }
 
         Ok(())
     }
 
+    pub fn get_edits(&self, username: &str, id: &str) -> Result<Vec<Edit>> {
+        let mut key = username.as_bytes().to_vec();
+        key.push(0xff);
+        key.extend_from_slice(id.as_bytes());
+
+        Ok(self
+            .usernameid_edits
+            .get(&key)?
+            .and_then(|x| rmps::from_read_ref(&x).ok())
+            .unwrap_or_default())
+    }
+
+    /// Stores the thumbnail image of an activity, along with the hash of the
+    /// track it was rendered from.
+    pub fn set_thumbnail(&self, username: &str, id: &str, hash: u64, image: &[u8]) -> Result<()> {
+        let mut key = username.as_bytes().to_vec();
+        key.push(0xff);
+        key.extend_from_slice(id.as_bytes());
+
+        let mut value = hash.to_be_bytes().to_vec();
+        value.extend_from_slice(image);
+        self.usernameid_thumbnail.insert(&key, value)?;
+
+        Ok(())
+    }
+
+    /// Gets the hash of the track the thumbnail was rendered from, and the image.
+    pub fn get_thumbnail(&self, username: &str, id: &str) -> Result<Option<(u64, Vec<u8>)>> {
+        let mut key = username.as_bytes().to_vec();
+        key.push(0xff);
+        key.extend_from_slice(id.as_bytes());
+
+        Ok(self.usernameid_thumbnail.get(&key)?.and_then(|x| {
+            let hash = u64::from_be_bytes(x.get(..8)?.try_into().ok()?);
+            Some((hash, x[8..].to_vec()))
+        }))
+    }
+
+    pub fn remove_thumbnail(&self, username: &str, id: &str) -> Result<()> {
+        let mut key = username.as_bytes().to_vec();
+        key.push(0xff);
+        key.extend_from_slice(id.as_bytes());
+
+        self.usernameid_thumbnail.remove(&key)?;
+
+        Ok(())
+    }
+
+    /// Stores the raw record of a cleaned track, along with the cleaning summary.
+    pub fn insert_raw(
+        &self,
+        username: &str,
+        id: &str,
+        cleaning: &Cleaning,
+        raw: &Record,
+    ) -> Result<()> {
+        let mut key = username.as_bytes().to_vec();
+        key.push(0xff);
+        key.extend_from_slice(id.as_bytes());
+
+        let cleaning = rmps::to_vec(cleaning)?;
+        self.usernameid_cleaning.insert(&key, cleaning)?;
+
+        let raw = rmps::to_vec(raw)?;
+        self.usernameid_rawrecord.insert(&key, raw)?;
+
+        Ok(())
+    }
+
+    /// Gets the raw record of a cleaned track, along with the cleaning summary.
+    pub fn get_raw(&self, username: &str, id: &str) -> Result<Option<(Cleaning, Record)>> {
+        let mut key = username.as_bytes().to_vec();
+        key.push(0xff);
+        key.extend_from_slice(id.as_bytes());
+
+        let cleaning = self
+            .usernameid_cleaning
+            .get(&key)?
+            .and_then(|x| rmps::from_read_ref(&x).ok());
+        let raw = self
+            .usernameid_rawrecord
+            .get(&key)?
+            .and_then(|x| rmps::from_read_ref(&x).ok());
+
+        Ok(cleaning.zip(raw))
+    }
+
+    /// Removes and returns the raw record of a cleaned track.
+    pub fn take_raw(&self, username: &str, id: &str) -> Result<Option<(Cleaning, Record)>> {
+        let mut key = username.as_bytes().to_vec();
+        key.push(0xff);
+        key.extend_from_slice(id.as_bytes());
+
+        let cleaning = self
+            .usernameid_cleaning
+            .remove(&key)?
+            .and_then(|x| rmps::from_read_ref(&x).ok());
+        let raw = self
+            .usernameid_rawrecord
+            .remove(&key)?
+            .and_then(|x| rmps::from_read_ref(&x).ok());
+
+        Ok(cleaning.zip(raw))
+    }
+
+    pub fn get_cleaning(&self, username: &str, id: &str) -> Result<Option<Cleaning>> {
+        let mut key = username.as_bytes().to_vec();
+        key.push(0xff);
+        key.extend_from_slice(id.as_bytes());
+
+        Ok(self
+            .usernameid_cleaning
+            .get(&key)?
+            .and_then(|x| rmps::from_read_ref(&x).ok()))
+    }
+
+    /// Updates the personal record history of a user, which has to be done
+    /// whenever an activity is added, changed or removed. The efforts of the
+    /// activity are merged into it, unless it held a record, in which case
+    /// the history is rebuilt from every activity.
+    pub fn update_personal_records(&self, username: &str, id: &str) -> Result<()> {
+        let mut records = self.get_personal_records(username)?;
+        if records.iter().any(|x| x.activity_id == id) {
+            return self.rebuild_personal_records(username);
+        }
+        // Removed activities which held no record leave it as it is
+        if !self.exists(username, id)? {
+            return Ok(());
+        }
+
+        let session = self.get_session(username, id)?;
+        efforts::merge(&mut records, id, &session, &self.get_efforts(username, id)?);
+
+        let records = rmps::to_vec(&records)?;
+        self.username_personalrecords.insert(username, records)?;
+
+        Ok(())
+    }
+
+    fn rebuild_personal_records(&self, username: &str) -> Result<()> {
+        let mut activities = Vec::new();
+        for (id, session) in self
+            .username_iter_id(username)?
+            .zip(self.username_iter_session(username)?)
+        {
+            let efforts = self.get_efforts(username, &id)?;
+            activities.push((id, session, efforts));
+        }
+
+        // Ids are start times, so the reversed iterators are in reverse chronological order
+        let records = efforts::progression(activities.into_iter().rev());
+
+        let records = rmps::to_vec(&records)?;
+        self.username_personalrecords.insert(username, records)?;
+
+        Ok(())
+    }
+
+    pub fn set_training_load(&self, username: &str, id: &str, load: Option<f64>) -> Result<()> {
+        let mut key = username.as_bytes().to_vec();
+        key.push(0xff);
+        key.extend_from_slice(id.as_bytes());
+
+        let load = rmps::to_vec(&load)?;
+        self.usernameid_load.insert(&key, load)?;
+
+        Ok(())
+    }
+
+    pub fn get_training_load(&self, username: &str, id: &str) -> Result<Option<f64>> {
+        let mut key = username.as_bytes().to_vec();
+        key.push(0xff);
+        key.extend_from_slice(id.as_bytes());
+
+        Ok(self
+            .usernameid_load
+            .get(&key)?
+            .and_then(|x| rmps::from_read_ref(&x).ok())
+            .flatten())
+    }
+
+    /// Recalculates the training load of every activity of a user,
+    /// which has to be done whenever the thresholds change.
+    pub fn update_training_load(
+        &self,
+        username: &str,
+        thresholds: &fitness::Thresholds,
+    ) -> Result<()> {
+        for id in self.username_iter_id(username)? {
+            let record = self.get_record(username, &id)?;
+            self.set_training_load(username, &id, fitness::training_load(&record, thresholds))?;
+        }
+
+        Ok(())
+    }
+
+    /// Calculates the training load of the activities which have none stored,
+    /// like the ones uploaded before it was calculated.
+    pub fn load_missing(&self, users: &super::users::UserTree) -> Result<()> {
+        for key in self.usernameid_session.iter().keys().flatten() {
+            if self.usernameid_load.contains_key(&key)? {
+                continue;
+            }
+
+            let mut parts = key.split(|x| x == &0xff);
+            let (username, id) = match (parts.next(), parts.next()) {
+                (Some(x), Some(y)) => (String::from_utf8_lossy(x), String::from_utf8_lossy(y)),
+                _ => continue,
+            };
+            let load = fitness::training_load(
+                &self.get_record(&username, &id)?,
+                &users.get_thresholds(&username)?,
+            );
+            self.set_training_load(&username, &id, load)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn get_personal_records(&self, username: &str) -> Result<Vec<PersonalRecord>> {
+        Ok(self
+            .username_personalrecords
+            .get(username)?
+            .and_then(|x| rmps::from_read_ref(&x).ok())
+            .unwrap_or_default())
+    }
+
     pub fn user_totals(&self, username: &str) -> Result<UserTotals> {
         let iter = self
             .username_iter_session(username)?
@@ -165,6 +579,47 @@ impl ActivityTree {
             .flat_map(|x| rmps::from_read_ref(&x)))
     }
 
+    /// Amount of activities of a user.
+    pub fn count(&self, username: &str) -> Result<usize> {
+        Ok(self
+            .username_activitycount
+            .get(username)?
+            .and_then(|x| x.as_ref().try_into().ok())
+            .map_or(0, u64::from_be_bytes) as usize)
+    }
+
+    fn add_to_count(&self, username: &str, change: i64) -> Result<()> {
+        self.username_activitycount
+            .update_and_fetch(username, |x| {
+                let count = x
+                    .and_then(|x| x.try_into().ok())
+                    .map_or(0, u64::from_be_bytes);
+                let count = (count as i64 + change).max(0) as u64;
+                Some(count.to_be_bytes().to_vec())
+            })?;
+
+        Ok(())
+    }
+
+    /// Counts the activities of every user again, which keeps the amounts
+    /// right if the server stopped between storing an activity and counting it.
+    pub fn count_all(&self) -> Result<()> {
+        let mut counts: std::collections::HashMap<Vec<u8>, u64> = Default::default();
+        for key in self.usernameid_session.iter().keys() {
+            let key = key?;
+            let username = key.split(|x| x == &0xff).next().unwrap_or_default();
+            *counts.entry(username.to_vec()).or_default() += 1;
+        }
+
+        self.username_activitycount.clear()?;
+        for (username, count) in counts {
+            self.username_activitycount
+                .insert(username, &count.to_be_bytes())?;
+        }
+
+        Ok(())
+    }
+
     pub fn username_iter_id(&self, username: &str) -> Result<impl Iterator<Item = String>> {
         let mut prefix = username.as_bytes().to_vec();
         prefix.push(0xff);
@@ -223,6 +678,20 @@ impl ActivityTree {
             .ok_or(Error::BadRequest(ErrorKind::NotFound, "Record not found"))
     }
 
+    /// Version of the record, which changes whenever it is written.
+    pub fn get_record_version(&self, username: &str, id: &str) -> Result<u64> {
+        let mut key = username.as_bytes().to_vec();
+        key.push(0xff);
+        key.extend_from_slice(id.as_bytes());
+
+        // Records written before versions were counted are at version 0
+        Ok(self
+            .usernameid_recordversion
+            .get(&key)?
+            .and_then(|x| x.as_ref().try_into().ok())
+            .map_or(0, u64::from_be_bytes))
+    }
+
     pub fn get_lap(&self, username: &str, id: &str) -> Result<Vec<Lap>> {
         let mut key = username.as_bytes().to_vec();
         key.push(0xff);
@@ -234,6 +703,29 @@ impl ActivityTree {
             .ok_or(Error::BadRequest(ErrorKind::NotFound, "Laps not found"))
     }
 
+    pub fn get_curve(&self, username: &str, id: &str) -> Result<Curve> {
+        let mut key = username.as_bytes().to_vec();
+        key.push(0xff);
+        key.extend_from_slice(id.as_bytes());
+
+        self.usernameid_curve
+            .get(&key)?
+            .and_then(|x| rmps::from_read_ref(&x).ok())
+            .ok_or(Error::BadRequest(ErrorKind::NotFound, "Curve not found"))
+    }
+
+    pub fn get_efforts(&self, username: &str, id: &str) -> Result<Vec<BestEffort>> {
+        let mut key = username.as_bytes().to_vec();
+        key.push(0xff);
+        key.extend_from_slice(id.as_bytes());
+
+        Ok(self
+            .usernameid_efforts
+            .get(&key)?
+            .and_then(|x| rmps::from_read_ref(&x).ok())
+            .unwrap_or_default())
+    }
+
     pub fn get_gear_id(&self, username: &str, id: &str) -> Result<Option<String>> {
         let mut key = username.as_bytes().to_vec();
         key.push(0xff);
@@ -257,6 +749,18 @@ impl ActivityTree {
             .and_then(|x| String::from_utf8(x).ok()))
     }
 
+    pub fn get_title(&self, username: &str, id: &str) -> Result<Option<String>> {
+        let mut key = username.as_bytes().to_vec();
+        key.push(0xff);
+        key.extend_from_slice(id.as_bytes());
+
+        Ok(self
+            .usernameid_title
+            .get(&key)?
+            .map(|x| x.to_vec())
+            .and_then(|x| String::from_utf8(x).ok()))
+    }
+
     pub fn get_activity(&self, username: &str, id: &str) -> Result<Activity> {
         Ok(Activity {
             id: id.to_owned(),
@@ -264,7 +768,41 @@ impl ActivityTree {
             session: self.get_session(username, id)?,
             record: self.get_record(username, id)?,
             lap: self.get_lap(username, id)?,
+            curve: self.get_curve(username, id).unwrap_or_default(),
+            efforts: self.get_efforts(username, id)?,
             notes: self.get_notes(username, id)?,
+            title: self.get_title(username, id)?,
         })
     }
 }
+
+/// Stores a text cut to the length, or removes it if there is none, and
+/// returns what was stored.
+fn set_text(
+    tree: &sled::Tree,
+    key: &[u8],
+    text: Option<String>,
+    length: usize,
+) -> Result<Option<String>> {
+    let text = text.map(|mut x| {
+        truncate(&mut x, length);
+        x
+    });
+    match &text {
+        Some(x) => tree.insert(key, x.as_bytes())?,
+        None => tree.remove(key)?,
+    };
+
+    Ok(text)
+}
+
+/// Shortens the text to at most `length` bytes, without splitting a character.
+fn truncate(text: &mut String, length: usize) {
+    if text.len() > length {
+        let end = (0..=length)
+            .rev()
+            .find(|x| text.is_char_boundary(*x))
+            .unwrap_or(0);
+        text.truncate(end);
+    }
+}
@@ -0,0 +1,382 @@
+use super::activities::ActivityTree;
+use crate::{
+    error::Result,
+    models::{Duration, Session},
+};
+use chrono::{DateTime, Local};
+use rmp_serde as rmps;
+use std::collections::BTreeSet;
+use uom::si::{f64::Length, length::meter};
+
+// Kinds of the terms activities are indexed by. Text terms are followed by
+// 0xff, which is never part of UTF-8, and numbers are big-endian so they sort
+// by value.
+const WORD: u8 = b'w';
+const TYPE: u8 = b't';
+const GEAR: u8 = b'g';
+const START: u8 = b's';
+const DISTANCE: u8 = b'd';
+const DURATION: u8 = b'u';
+
+/// Filters for the activities of a user. Filters left out match every
+/// activity, and ranges include both ends.
+#[derive(Default)]
+pub struct Filter {
+    /// Starts of words in the title or the description, which all have to match
+    pub words: Vec<String>,
+    pub activity_type: Option<String>,
+    pub gear: Option<String>,
+    pub start: (Option<DateTime<Local>>, Option<DateTime<Local>>),
+    pub distance: (Option<Length>, Option<Length>),
+    pub duration: (Option<Duration>, Option<Duration>),
+}
+
+/// Values of activities which are indexed in order.
+#[derive(Clone, Copy)]
+pub enum Indexed {
+    Start,
+    Distance,
+    Duration,
+}
+
+impl Indexed {
+    fn kind(self) -> u8 {
+        match self {
+            Self::Start => START,
+            Self::Distance => DISTANCE,
+            Self::Duration => DURATION,
+        }
+    }
+}
+
+impl ActivityTree {
+    /// Indexes an activity by the words of its title and description, its
+    /// type, gear, start time, distance and duration, in place of what it was
+    /// indexed by before.
+    pub(super) fn index(
+        &self,
+        username: &str,
+        id: &str,
+        session: &Session,
+        gear_id: Option<&str>,
+        title: Option<&str>,
+        notes: Option<&str>,
+    ) -> Result<()> {
+        self.unindex(username, id)?;
+
+        let mut terms: Vec<(u8, Vec<u8>)> = Vec::new();
+        let default_title = session.default_title();
+        let text = [title.unwrap_or(&default_title), notes.unwrap_or_default()];
+        let words: BTreeSet<String> = text
+            .iter()
+            .flat_map(|x| x.split(|y: char| !y.is_alphanumeric()))
+            .filter(|x| !x.is_empty())
+            .map(str::to_lowercase)
+            .collect();
+        terms.extend(words.into_iter().map(|x| (WORD, text_term(&x))));
+
+        terms.push((TYPE, text_term(&session.activity_type.to_string())));
+        if let Some(x) = gear_id {
+            terms.push((GEAR, text_term(x)));
+        }
+        terms.push((START, number_term(start_value(&session.start_time.0))));
+        if let Some(x) = session.distance {
+            terms.push((DISTANCE, number_term(distance_value(x))));
+        }
+        terms.push((
+            DURATION,
+            number_term(duration_value(session.duration_active)),
+        ));
+
+        let keys: Vec<Vec<u8>> = terms
+            .into_iter()
+            .map(|(kind, term)| {
+                let mut key = prefix(username, kind);
+                key.extend_from_slice(&term);
+                key.extend_from_slice(id.as_bytes());
+                key
+            })
+            .collect();
+        for key in &keys {
+            self.usernametermid.insert(key, &[])?;
+        }
+        self.usernameid_terms
+            .insert(key(username, id), rmps::to_vec(&keys)?)?;
+
+        Ok(())
+    }
+
+    /// Removes an activity from the index.
+    pub(super) fn unindex(&self, username: &str, id: &str) -> Result<()> {
+        if let Some(x) = self.usernameid_terms.remove(key(username, id))? {
+            let keys: Vec<Vec<u8>> = rmps::from_read_ref(&x).unwrap_or_default();
+            for key in keys {
+                self.usernametermid.remove(key)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Indexes the activities stored before they were indexed.
+    pub fn index_missing(&self) -> Result<()> {
+        for key in self.usernameid_session.iter().keys().flatten() {
+            if self.usernameid_terms.contains_key(&key)? {
+                continue;
+            }
+
+            let mut parts = key.split(|x| x == &0xff);
+            let (username, id) = match (parts.next(), parts.next()) {
+                (Some(x), Some(y)) => (String::from_utf8_lossy(x), String::from_utf8_lossy(y)),
+                _ => continue,
+            };
+            self.index(
+                &username,
+                &id,
+                &self.get_session(&username, &id)?,
+                self.get_gear_id(&username, &id)?.as_deref(),
+                self.get_title(&username, &id)?.as_deref(),
+                self.get_notes(&username, &id)?.as_deref(),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Ids of the activities of a user which match the filter, or None if
+    /// nothing is filtered.
+    pub fn filter(&self, username: &str, filter: &Filter) -> Result<Option<BTreeSet<String>>> {
+        let mut matches: Vec<BTreeSet<String>> = Vec::new();
+
+        // A word of the filter is the start of any word of an activity
+        for word in &filter.words {
+            let mut key = prefix(username, WORD);
+            key.extend_from_slice(word.to_lowercase().as_bytes());
+            matches.push(self.ids(self.usernametermid.scan_prefix(key))?);
+        }
+        if let Some(x) = &filter.activity_type {
+            matches.push(self.exact(username, TYPE, x)?);
+        }
+        if let Some(x) = &filter.gear {
+            matches.push(self.exact(username, GEAR, x)?);
+        }
+
+        let ranges = [
+            (
+                START,
+                filter.start.0.as_ref().map(start_value),
+                filter.start.1.as_ref().map(start_value),
+            ),
+            (
+                DISTANCE,
+                filter.distance.0.map(distance_value),
+                filter.distance.1.map(distance_value),
+            ),
+            (
+                DURATION,
+                filter.duration.0.map(duration_value),
+                filter.duration.1.map(duration_value),
+            ),
+        ];
+        for (kind, min, max) in ranges.iter().copied() {
+            if min.is_none() && max.is_none() {
+                continue;
+            }
+
+            let mut start = prefix(username, kind);
+            start.extend_from_slice(&min.unwrap_or(0).to_be_bytes());
+            // Past the greatest value, or past every value of the kind
+            let end = match max.and_then(|x| x.checked_add(1)) {
+                Some(x) => {
+                    let mut end = prefix(username, kind);
+                    end.extend_from_slice(&x.to_be_bytes());
+                    end
+                }
+                None => prefix(username, kind + 1),
+            };
+            matches.push(self.ids(self.usernametermid.range(start..end))?);
+        }
+
+        Ok(matches
+            .into_iter()
+            .reduce(|x, y| x.intersection(&y).cloned().collect()))
+    }
+
+    /// Ids of the activities of a user which have the value, from the lowest
+    /// value to the highest.
+    pub fn sorted(&self, username: &str, indexed: Indexed) -> Result<Vec<String>> {
+        self.usernametermid
+            .scan_prefix(prefix(username, indexed.kind()))
+            .keys()
+            .map(|x| Ok(id(&x?)))
+            .collect()
+    }
+
+    /// Activity types of the activities of a user.
+    pub fn activity_types(&self, username: &str) -> Result<BTreeSet<String>> {
+        let start = prefix(username, TYPE).len();
+
+        self.usernametermid
+            .scan_prefix(prefix(username, TYPE))
+            .keys()
+            .map(|x| {
+                let x = x?;
+                let end = start + x[start..].iter().take_while(|y| **y != 0xff).count();
+                Ok(String::from_utf8_lossy(&x[start..end]).into_owned())
+            })
+            .collect()
+    }
+
+    fn exact(&self, username: &str, kind: u8, value: &str) -> Result<BTreeSet<String>> {
+        let mut key = prefix(username, kind);
+        key.extend_from_slice(&text_term(value));
+        self.ids(self.usernametermid.scan_prefix(key))
+    }
+
+    fn ids(&self, iter: sled::Iter) -> Result<BTreeSet<String>> {
+        iter.keys().map(|x| Ok(id(&x?))).collect()
+    }
+}
+
+fn prefix(username: &str, kind: u8) -> Vec<u8> {
+    let mut key = username.as_bytes().to_vec();
+    key.push(0xff);
+    key.push(kind);
+    key
+}
+
+fn key(username: &str, id: &str) -> Vec<u8> {
+    let mut key = username.as_bytes().to_vec();
+    key.push(0xff);
+    key.extend_from_slice(id.as_bytes());
+    key
+}
+
+/// Id of the activity at the end of a key of the index.
+fn id(key: &[u8]) -> String {
+    String::from_utf8_lossy(key.split(|x| x == &0xff).next_back().unwrap_or_default()).into_owned()
+}
+
+fn text_term(value: &str) -> Vec<u8> {
+    let mut term = value.as_bytes().to_vec();
+    term.push(0xff);
+    term
+}
+
+fn number_term(value: u64) -> Vec<u8> {
+    let mut term = value.to_be_bytes().to_vec();
+    term.push(0xff);
+    term
+}
+
+fn start_value(x: &DateTime<Local>) -> u64 {
+    x.timestamp().max(0) as u64
+}
+
+fn distance_value(x: Length) -> u64 {
+    x.get::<meter>().max(0.).round() as u64
+}
+
+fn duration_value(x: Duration) -> u64 {
+    x.as_secs_f64().max(0.).round() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree() -> ActivityTree {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let tree = |x: &str| db.open_tree(x).unwrap();
+
+        ActivityTree {
+            usernameid_gearid: tree("usernameid_gearid"),
+            usernameid_session: tree("usernameid_session"),
+            usernameid_record: tree("usernameid_record"),
+            usernameid_rawrecord: tree("usernameid_rawrecord"),
+            usernameid_cleaning: tree("usernameid_cleaning"),
+            usernameid_lap: tree("usernameid_lap"),
+            usernameid_curve: tree("usernameid_curve"),
+            usernameid_efforts: tree("usernameid_efforts"),
+            usernameid_load: tree("usernameid_load"),
+            usernameid_notes: tree("usernameid_notes"),
+            usernameid_title: tree("usernameid_title"),
+            usernameid_thumbnail: tree("usernameid_thumbnail"),
+            usernameid_edits: tree("usernameid_edits"),
+            usernameid_recordversion: tree("usernameid_recordversion"),
+            usernametermid: tree("usernametermid"),
+            usernameid_terms: tree("usernameid_terms"),
+            username_personalrecords: tree("username_personalrecords"),
+            username_activitycount: tree("username_activitycount"),
+        }
+    }
+
+    /// Activities of 1, 2 and 3 kilometers, with ids to match.
+    fn indexed() -> ActivityTree {
+        let tree = tree();
+        for x in 1..=3 {
+            let session = Session {
+                distance: Some(Length::new::<meter>(f64::from(x) * 1000.)),
+                ..Default::default()
+            };
+            tree.index("user", &x.to_string(), &session, None, None, None)
+                .unwrap();
+        }
+
+        tree
+    }
+
+    fn distance(tree: &ActivityTree, min: Option<f64>, max: Option<f64>) -> Vec<String> {
+        let filter = Filter {
+            distance: (min.map(Length::new::<meter>), max.map(Length::new::<meter>)),
+            ..Default::default()
+        };
+
+        tree.filter("user", &filter)
+            .unwrap()
+            .unwrap()
+            .into_iter()
+            .collect()
+    }
+
+    #[test]
+    fn filter_range_includes_bounds() {
+        let tree = indexed();
+
+        assert_eq!(distance(&tree, Some(2000.), Some(2000.)), vec!["2"]);
+        assert_eq!(
+            distance(&tree, Some(1000.), Some(3000.)),
+            vec!["1", "2", "3"]
+        );
+    }
+
+    #[test]
+    fn filter_range_open_ends() {
+        let tree = indexed();
+
+        assert_eq!(distance(&tree, None, Some(2000.)), vec!["1", "2"]);
+        assert_eq!(distance(&tree, Some(2000.), None), vec!["2", "3"]);
+        assert!(distance(&tree, Some(3001.), None).is_empty());
+    }
+
+    #[test]
+    fn filter_range_stays_within_kind() {
+        let tree = indexed();
+
+        // Durations are indexed after distances, and are all zero
+        let filter = Filter {
+            duration: (None, Some(Duration::from_secs_f64(0.))),
+            ..Default::default()
+        };
+        assert_eq!(tree.filter("user", &filter).unwrap().unwrap().len(), 3);
+        assert_eq!(distance(&tree, Some(0.), Some(500.)), Vec::<String>::new());
+    }
+
+    #[test]
+    fn nothing_filtered() {
+        assert!(indexed()
+            .filter("user", &Filter::default())
+            .unwrap()
+            .is_none());
+    }
+}
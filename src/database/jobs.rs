@@ -0,0 +1,230 @@
+use crate::{error::Result, jobs::Job};
+use rmp_serde as rmps;
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// How often workers look again for a job when every queued one belongs to
+/// a user who already has a job running.
+const BLOCKED_POLL: Duration = Duration::from_millis(100);
+
+#[derive(Clone)]
+pub struct JobTree {
+    pub(super) db: sled::Db,
+    pub(super) jobid_job: sled::Tree,
+    pub(super) jobid_running: sled::Tree,
+    pub(super) jobid_failed: sled::Tree,
+    /// Username, 0xff, serialized job and its id, for every queued or
+    /// running job
+    pub(super) job_jobid: sled::Tree,
+    /// Users who have a job running, which is taken by one worker at a time
+    pub(super) running_users: Arc<Mutex<HashSet<String>>>,
+}
+
+impl JobTree {
+    pub fn push(&self, job: &Job) -> Result<()> {
+        let id = self.db.generate_id()?.to_be_bytes();
+
+        self.job_jobid
+            .insert([&index_prefix(job)?[..], &id].concat(), &[])?;
+        self.jobid_job.insert(id, rmps::to_vec(job)?)?;
+
+        Ok(())
+    }
+
+    /// Takes the oldest queued job and marks it as running, or waits for one
+    /// to be queued until the timeout. Every job is taken by one worker only,
+    /// and jobs of a user who already has one running are left queued.
+    pub fn take(&self, timeout: Duration) -> Result<Option<(sled::IVec, Job)>> {
+        let subscriber = self.jobid_job.watch_prefix(vec![]);
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            if let Some(x) = self.take_unblocked()? {
+                return Ok(Some(x));
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+
+            let wait = match self.jobid_job.is_empty() {
+                true => deadline - now,
+                false => BLOCKED_POLL.min(deadline - now),
+            };
+            let _ = subscriber.next_timeout(wait);
+        }
+    }
+
+    /// Takes the oldest job of the first user in the index who has none
+    /// running. Users who do are skipped without reading their jobs.
+    fn take_unblocked(&self) -> Result<Option<(sled::IVec, Job)>> {
+        let mut running_users = self.running_users.lock().unwrap();
+        let mut start = Vec::new();
+
+        loop {
+            let key = match self.job_jobid.range(&start[..]..).keys().next() {
+                Some(x) => x?,
+                None => return Ok(None),
+            };
+            let username = key.split(|x| *x == 0xff).next().unwrap_or_default();
+            let prefix = [username, &[0xff]].concat();
+
+            if running_users.contains(&*String::from_utf8_lossy(username)) {
+                // Usernames contain no 0xff and serialized jobs don't start
+                // with it, so this is after every job of the user
+                start = [&prefix[..], &[0xff]].concat();
+                continue;
+            }
+
+            // Ids are at the end of the keys, and generated in order
+            let oldest = self
+                .job_jobid
+                .scan_prefix(&prefix)
+                .keys()
+                .filter_map(|x| x.ok()?.rchunks(8).next().map(<[u8]>::to_vec))
+                .min();
+            let id = match oldest {
+                Some(x) => x,
+                None => continue,
+            };
+
+            // A job which was removed without its index, or can't be read,
+            // is dropped from the index
+            let job = self
+                .jobid_job
+                .remove(&id)?
+                .map(|x| rmps::from_read_ref::<_, Job>(&x).map(|y| (x, y)));
+            match job {
+                Some(Ok((serialized, job))) => {
+                    self.jobid_running.insert(&id, serialized)?;
+                    running_users.insert(job.username().to_owned());
+
+                    return Ok(Some((id.into(), job)));
+                }
+                _ => {
+                    for x in self.job_jobid.scan_prefix(&prefix).keys() {
+                        let x = x?;
+                        if x.ends_with(&id) {
+                            self.job_jobid.remove(x)?;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn finish(&self, key: &sled::IVec, job: &Job) -> Result<()> {
+        self.running_users.lock().unwrap().remove(job.username());
+
+        self.job_jobid
+            .remove([&index_prefix(job)?[..], key].concat())?;
+        self.jobid_running.remove(key)?;
+
+        Ok(())
+    }
+
+    /// Finishes a job which failed, keeping it with the error.
+    pub fn fail(&self, key: &sled::IVec, job: &Job, error: &str) -> Result<()> {
+        self.jobid_failed
+            .insert(key, rmps::to_vec(&(job, error))?)?;
+
+        self.finish(key, job)
+    }
+
+    /// Queues the jobs which were running when the server stopped again.
+    pub fn requeue_running(&self) -> Result<()> {
+        for x in self.jobid_running.iter() {
+            let (key, job) = x?;
+            self.jobid_job.insert(&key, job)?;
+            self.jobid_running.remove(&key)?;
+        }
+
+        Ok(())
+    }
+
+    /// Whether an identical job is queued or running.
+    pub fn is_pending(&self, job: &Job) -> Result<bool> {
+        // Serialized jobs don't prefix each other, so any key starting with
+        // the job is followed by the id of an identical job
+        Ok(self
+            .job_jobid
+            .scan_prefix(index_prefix(job)?)
+            .next()
+            .transpose()?
+            .is_some())
+    }
+}
+
+/// Start of the keys of the job in the index, which are grouped by user.
+fn index_prefix(job: &Job) -> Result<Vec<u8>> {
+    Ok([job.username().as_bytes(), &[0xff], &rmps::to_vec(job)?].concat())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree() -> JobTree {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let tree = |x: &str| db.open_tree(x).unwrap();
+
+        JobTree {
+            jobid_job: tree("jobid_job"),
+            jobid_running: tree("jobid_running"),
+            jobid_failed: tree("jobid_failed"),
+            job_jobid: tree("job_jobid"),
+            running_users: Default::default(),
+            db,
+        }
+    }
+
+    fn analyze(username: &str, id: &str) -> Job {
+        Job::Analyze {
+            username: username.to_string(),
+            id: id.to_string(),
+        }
+    }
+
+    fn take(tree: &JobTree) -> Option<(sled::IVec, Job)> {
+        tree.take(Duration::from_millis(0)).unwrap()
+    }
+
+    #[test]
+    fn take_runs_one_job_per_user() {
+        let tree = tree();
+        for job in &[analyze("b", "1"), analyze("b", "2"), analyze("a", "3")] {
+            tree.push(job).unwrap();
+        }
+
+        let (first, job) = take(&tree).unwrap();
+        assert_eq!(job.username(), "a");
+        let (second, job) = take(&tree).unwrap();
+        assert!(matches!(job, Job::Analyze { id, .. } if id == "1"));
+        // Both users have a job running
+        assert!(take(&tree).is_none());
+
+        tree.finish(&first, &analyze("a", "3")).unwrap();
+        assert!(take(&tree).is_none());
+        tree.finish(&second, &analyze("b", "1")).unwrap();
+        let (_, job) = take(&tree).unwrap();
+        assert!(matches!(job, Job::Analyze { id, .. } if id == "2"));
+    }
+
+    #[test]
+    fn is_pending_until_finished() {
+        let tree = tree();
+        tree.push(&analyze("a", "1")).unwrap();
+
+        assert!(tree.is_pending(&analyze("a", "1")).unwrap());
+        assert!(!tree.is_pending(&analyze("a", "2")).unwrap());
+
+        let (key, job) = take(&tree).unwrap();
+        assert!(tree.is_pending(&job).unwrap());
+        tree.finish(&key, &job).unwrap();
+        assert!(!tree.is_pending(&job).unwrap());
+    }
+}
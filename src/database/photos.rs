@@ -0,0 +1,128 @@
+use crate::{
+    error::{Error, ErrorKind, Result},
+    models::Photo,
+};
+use rmp_serde as rmps;
+
+#[derive(Clone)]
+pub struct PhotoTree {
+    pub(super) db: sled::Db,
+    pub(super) usernameidphotoid_photo: sled::Tree,
+    pub(super) usernameidphotoid_image: sled::Tree,
+    pub(super) usernameidphotoid_thumbnail: sled::Tree,
+}
+
+impl PhotoTree {
+    /// Stores a photo of an activity with its thumbnail, and returns the id
+    /// of the photo. Ids are never reused, so images can be cached for good.
+    pub fn insert(
+        &self,
+        username: &str,
+        activity_id: &str,
+        photo: &Photo,
+        image: &[u8],
+        thumbnail: &[u8],
+    ) -> Result<String> {
+        let id = self.db.generate_id()?.to_string();
+        let key = key(username, activity_id, &id);
+
+        self.usernameidphotoid_image.insert(&key, image)?;
+        self.usernameidphotoid_thumbnail.insert(&key, thumbnail)?;
+        self.usernameidphotoid_photo
+            .insert(&key, rmps::to_vec(photo)?)?;
+
+        Ok(id)
+    }
+
+    pub fn get(&self, username: &str, activity_id: &str, id: &str) -> Result<Photo> {
+        self.usernameidphotoid_photo
+            .get(key(username, activity_id, id))?
+            .and_then(|x| rmps::from_read_ref(&x).ok())
+            .ok_or(Error::BadRequest(ErrorKind::NotFound, "Photo not found"))
+    }
+
+    pub fn get_image(&self, username: &str, activity_id: &str, id: &str) -> Result<Vec<u8>> {
+        self.usernameidphotoid_image
+            .get(key(username, activity_id, id))?
+            .map(|x| x.to_vec())
+            .ok_or(Error::BadRequest(ErrorKind::NotFound, "Photo not found"))
+    }
+
+    /// Gets the thumbnail of a photo, which is a JPEG image.
+    pub fn get_thumbnail(&self, username: &str, activity_id: &str, id: &str) -> Result<Vec<u8>> {
+        self.usernameidphotoid_thumbnail
+            .get(key(username, activity_id, id))?
+            .map(|x| x.to_vec())
+            .ok_or(Error::BadRequest(ErrorKind::NotFound, "Photo not found"))
+    }
+
+    /// Iterates over the photos of an activity as (id, photo), in the order
+    /// they were added.
+    pub fn iter(
+        &self,
+        username: &str,
+        activity_id: &str,
+    ) -> Result<impl Iterator<Item = (String, Photo)>> {
+        Ok(self
+            .usernameidphotoid_photo
+            .scan_prefix(prefix(username, activity_id))
+            .flatten()
+            .filter_map(|(k, v)| {
+                let id = k.split(|x| x == &0xff).next_back()?.to_vec();
+                Some((String::from_utf8(id).ok()?, rmps::from_read_ref(&v).ok()?))
+            }))
+    }
+
+    pub fn remove(&self, username: &str, activity_id: &str, id: &str) -> Result<()> {
+        let key = key(username, activity_id, id);
+
+        self.usernameidphotoid_photo.remove(&key)?;
+        self.usernameidphotoid_image.remove(&key)?;
+        self.usernameidphotoid_thumbnail.remove(&key)?;
+
+        Ok(())
+    }
+
+    /// Removes every photo of an activity.
+    pub fn remove_activity(&self, username: &str, activity_id: &str) -> Result<()> {
+        for (id, _) in self.iter(username, activity_id)? {
+            self.remove(username, activity_id, &id)?;
+        }
+
+        Ok(())
+    }
+
+    /// Moves the photos of an activity to another one, for activities merged
+    /// into others.
+    pub fn move_activity(&self, username: &str, from: &str, to: &str) -> Result<()> {
+        for (id, photo) in self.iter(username, from)? {
+            let (old, new) = (key(username, from, &id), key(username, to, &id));
+
+            if let Some(x) = self.usernameidphotoid_image.remove(&old)? {
+                self.usernameidphotoid_image.insert(&new, x)?;
+            }
+            if let Some(x) = self.usernameidphotoid_thumbnail.remove(&old)? {
+                self.usernameidphotoid_thumbnail.insert(&new, x)?;
+            }
+            self.usernameidphotoid_photo.remove(&old)?;
+            self.usernameidphotoid_photo
+                .insert(&new, rmps::to_vec(&photo)?)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn prefix(username: &str, activity_id: &str) -> Vec<u8> {
+    let mut key = username.as_bytes().to_vec();
+    key.push(0xff);
+    key.extend_from_slice(activity_id.as_bytes());
+    key.push(0xff);
+    key
+}
+
+fn key(username: &str, activity_id: &str, id: &str) -> Vec<u8> {
+    let mut key = prefix(username, activity_id);
+    key.extend_from_slice(id.as_bytes());
+    key
+}
@@ -0,0 +1,216 @@
+use super::activities::ActivityTree;
+use crate::{
+    analysis::routes,
+    error::{Error, ErrorKind, Result},
+    models::{Activity, ActivityType, Record, Route},
+};
+use rmp_serde as rmps;
+use uom::si::{f64::Length, length::meter};
+
+#[derive(Clone)]
+pub struct RouteTree {
+    pub(super) usernameid_route: sled::Tree,
+}
+
+impl RouteTree {
+    pub fn get(&self, username: &str, id: &str) -> Result<Route> {
+        self.usernameid_route
+            .get(key(username, id))?
+            .and_then(|x| rmps::from_read_ref(&x).ok())
+            .ok_or(Error::BadRequest(ErrorKind::NotFound, "Route not found"))
+    }
+
+    /// Iterates over the routes of a user as (id, route), newest first.
+    pub fn iter(&self, username: &str) -> Result<impl Iterator<Item = (String, Route)>> {
+        let mut prefix = username.as_bytes().to_vec();
+        prefix.push(0xff);
+
+        Ok(self
+            .usernameid_route
+            .scan_prefix(&prefix)
+            .rev()
+            .flatten()
+            .filter_map(|(k, v)| {
+                let id = k.split(|x| x == &0xff).next_back()?.to_vec();
+                Some((String::from_utf8(id).ok()?, rmps::from_read_ref(&v).ok()?))
+            }))
+    }
+
+    pub fn rename(&self, username: &str, id: &str, name: Option<String>) -> Result<()> {
+        let mut route = self.get(username, id)?;
+        route.name = name;
+        self.usernameid_route
+            .insert(key(username, id), rmps::to_vec(&route)?)?;
+
+        Ok(())
+    }
+
+    /// Adds an activity to the first route whose course it follows, or starts
+    /// a new route with it. Activities without GPS data are left out.
+    pub fn assign(&self, username: &str, activity: &Activity) -> Result<()> {
+        self.remove_activity(username, &activity.id)?;
+        self.assign_record(
+            username,
+            &activity.id,
+            &activity.session.activity_type,
+            &activity.record,
+        )
+    }
+
+    fn assign_record(
+        &self,
+        username: &str,
+        id: &str,
+        activity_type: &ActivityType,
+        record: &Record,
+    ) -> Result<()> {
+        let (track, length) = match routes::simplify(record) {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+
+        for (route_id, mut route) in self.iter(username)? {
+            if &route.activity_type != activity_type
+                || !routes::matches(&route.track, route.distance.get::<meter>(), &track, length)
+            {
+                continue;
+            }
+
+            route.activity_ids.push(id.to_string());
+            route.activity_ids.sort();
+            self.usernameid_route
+                .insert(key(username, &route_id), rmps::to_vec(&route)?)?;
+
+            return Ok(());
+        }
+
+        let route = Route {
+            name: None,
+            activity_type: activity_type.clone(),
+            track,
+            distance: Length::new::<meter>(length),
+            activity_ids: vec![id.to_string()],
+        };
+        self.usernameid_route
+            .insert(key(username, id), rmps::to_vec(&route)?)?;
+
+        Ok(())
+    }
+
+    /// Removes an activity from its route. The route keeps its course even
+    /// when the activity it was started with is removed, but is then keyed
+    /// by the oldest activity left on it.
+    pub fn remove_activity(&self, username: &str, id: &str) -> Result<()> {
+        for (route_id, mut route) in self.iter(username)? {
+            if !route.activity_ids.iter().any(|x| x == id) {
+                continue;
+            }
+
+            route.activity_ids.retain(|x| x != id);
+            self.usernameid_route.remove(key(username, &route_id))?;
+            if let Some(first) = route.activity_ids.first() {
+                let new_id = match route_id == id {
+                    true => first,
+                    false => &route_id,
+                };
+                self.usernameid_route
+                    .insert(key(username, new_id), rmps::to_vec(&route)?)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Groups all activities of a user into routes from scratch, oldest first.
+    /// Names of routes that start with the same activity are kept.
+    pub fn rebuild(&self, username: &str, activities: &ActivityTree) -> Result<()> {
+        let names = self
+            .iter(username)?
+            .filter_map(|(id, route)| Some((id, route.name?)))
+            .collect::<Vec<(String, String)>>();
+
+        for (id, _) in self.iter(username)? {
+            self.usernameid_route.remove(key(username, &id))?;
+        }
+
+        let mut ids = activities
+            .username_iter_id(username)?
+            .collect::<Vec<String>>();
+        ids.sort();
+
+        for id in ids {
+            let session = activities.get_session(username, &id)?;
+            let record = activities.get_record(username, &id)?;
+            self.assign_record(username, &id, &session.activity_type, &record)?;
+        }
+
+        for (id, name) in names {
+            if self.usernameid_route.contains_key(key(username, &id))? {
+                self.rename(username, &id, Some(name))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn key(username: &str, id: &str) -> Vec<u8> {
+    let mut key = username.as_bytes().to_vec();
+    key.push(0xff);
+    key.extend_from_slice(id.as_bytes());
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree() -> RouteTree {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+
+        RouteTree {
+            usernameid_route: db.open_tree("usernameid_route").unwrap(),
+        }
+    }
+
+    fn insert(tree: &RouteTree, ids: &[&str]) {
+        let route = Route {
+            name: None,
+            activity_type: ActivityType::default(),
+            track: Vec::new(),
+            distance: Length::new::<meter>(0.),
+            activity_ids: ids.iter().map(|x| x.to_string()).collect(),
+        };
+        tree.usernameid_route
+            .insert(key("user", ids[0]), rmps::to_vec(&route).unwrap())
+            .unwrap();
+    }
+
+    fn routes(tree: &RouteTree) -> Vec<(String, Vec<String>)> {
+        tree.iter("user")
+            .unwrap()
+            .map(|(id, route)| (id, route.activity_ids))
+            .collect()
+    }
+
+    #[test]
+    fn remove_first_activity_rekeys_route() {
+        let tree = tree();
+        insert(&tree, &["1", "2", "3"]);
+
+        tree.remove_activity("user", "1").unwrap();
+        assert_eq!(
+            routes(&tree),
+            vec![("2".to_string(), vec!["2".to_string(), "3".to_string()])]
+        );
+
+        tree.remove_activity("user", "3").unwrap();
+        assert_eq!(
+            routes(&tree),
+            vec![("2".to_string(), vec!["2".to_string()])]
+        );
+
+        tree.remove_activity("user", "2").unwrap();
+        assert!(routes(&tree).is_empty());
+    }
+}
@@ -0,0 +1,168 @@
+use super::activities::ActivityTree;
+use crate::{
+    analysis::segments,
+    error::{Error, ErrorKind, Result},
+    models::{Activity, Segment, SegmentEffort},
+};
+use rmp_serde as rmps;
+use std::convert::TryInto;
+
+#[derive(Clone)]
+pub struct SegmentTree {
+    pub(super) db: sled::Db,
+    pub(super) segmentid_segment: sled::Tree,
+    pub(super) segmentidusernameid_effort: sled::Tree,
+}
+
+impl SegmentTree {
+    pub fn insert(&self, segment: &Segment) -> Result<u64> {
+        let id = self.db.generate_id()?;
+        self.segmentid_segment
+            .insert(id.to_be_bytes(), rmps::to_vec(segment)?)?;
+
+        Ok(id)
+    }
+
+    pub fn exists(&self, id: u64) -> Result<bool> {
+        Ok(self.segmentid_segment.contains_key(id.to_be_bytes())?)
+    }
+
+    pub fn get(&self, id: u64) -> Result<Segment> {
+        self.segmentid_segment
+            .get(id.to_be_bytes())?
+            .and_then(|x| rmps::from_read_ref(&x).ok())
+            .ok_or(Error::BadRequest(ErrorKind::NotFound, "Segment not found"))
+    }
+
+    /// Iterates over all segments, newest first.
+    pub fn iter(&self) -> impl Iterator<Item = (u64, Segment)> {
+        self.segmentid_segment
+            .iter()
+            .rev()
+            .flatten()
+            .filter_map(|(k, v)| {
+                Some((
+                    u64::from_be_bytes(k.as_ref().try_into().ok()?),
+                    rmps::from_read_ref(&v).ok()?,
+                ))
+            })
+    }
+
+    pub fn remove(&self, id: u64) -> Result<()> {
+        self.segmentid_segment.remove(id.to_be_bytes())?;
+
+        for key in self
+            .segmentidusernameid_effort
+            .scan_prefix(id.to_be_bytes())
+            .keys()
+        {
+            self.segmentidusernameid_effort.remove(key?)?;
+        }
+
+        Ok(())
+    }
+
+    /// Matches a new segment against the activities of every user.
+    pub fn match_segment(&self, id: u64, activities: &ActivityTree) -> Result<()> {
+        let segment = self.get(id)?;
+        let keys = activities
+            .iter_username()?
+            .zip(activities.iter_id()?)
+            .collect::<Vec<(String, String)>>();
+
+        for (username, activity_id) in keys {
+            if activities
+                .get_session(&username, &activity_id)?
+                .activity_type
+                != segment.activity_type
+            {
+                continue;
+            }
+
+            let record = activities.get_record(&username, &activity_id)?;
+            if let Some(x) = segments::find_effort(&segment, &record) {
+                self.segmentidusernameid_effort
+                    .insert(key(id, &username, &activity_id), rmps::to_vec(&x)?)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Matches an activity against every segment, replacing its previous efforts.
+    pub fn update_efforts(&self, username: &str, activity: &Activity) -> Result<()> {
+        for (id, segment) in self.iter() {
+            let key = key(id, username, &activity.id);
+
+            match segment.activity_type == activity.session.activity_type {
+                true => match segments::find_effort(&segment, &activity.record) {
+                    Some(x) => self
+                        .segmentidusernameid_effort
+                        .insert(key, rmps::to_vec(&x)?)?,
+                    None => self.segmentidusernameid_effort.remove(key)?,
+                },
+                false => self.segmentidusernameid_effort.remove(key)?,
+            };
+        }
+
+        Ok(())
+    }
+
+    pub fn remove_efforts(&self, username: &str, activity_id: &str) -> Result<()> {
+        for (id, _) in self.iter() {
+            self.segmentidusernameid_effort
+                .remove(key(id, username, activity_id))?;
+        }
+
+        Ok(())
+    }
+
+    /// Efforts on a segment as (username, activity id, effort), fastest first.
+    pub fn get_efforts(&self, id: u64) -> Result<Vec<(String, String, SegmentEffort)>> {
+        let mut efforts = self
+            .segmentidusernameid_effort
+            .scan_prefix(id.to_be_bytes())
+            .flatten()
+            .filter_map(|(k, v)| {
+                let mut split = k[8..].split(|x| x == &0xff);
+                let username = String::from_utf8(split.next()?.to_vec()).ok()?;
+                let activity_id = String::from_utf8(split.next()?.to_vec()).ok()?;
+
+                Some((username, activity_id, rmps::from_read_ref(&v).ok()?))
+            })
+            .collect::<Vec<(String, String, SegmentEffort)>>();
+
+        efforts.sort_by(|a, b| a.2.duration.partial_cmp(&b.2.duration).unwrap());
+
+        Ok(efforts)
+    }
+
+    /// Efforts of an activity as (segment id, segment, effort).
+    pub fn get_activity_efforts(
+        &self,
+        username: &str,
+        activity_id: &str,
+    ) -> Result<Vec<(u64, Segment, SegmentEffort)>> {
+        let mut efforts = Vec::new();
+
+        for (id, segment) in self.iter() {
+            if let Some(x) = self
+                .segmentidusernameid_effort
+                .get(key(id, username, activity_id))?
+                .and_then(|x| rmps::from_read_ref(&x).ok())
+            {
+                efforts.push((id, segment, x));
+            }
+        }
+
+        Ok(efforts)
+    }
+}
+
+fn key(id: u64, username: &str, activity_id: &str) -> Vec<u8> {
+    let mut key = id.to_be_bytes().to_vec();
+    key.extend_from_slice(username.as_bytes());
+    key.push(0xff);
+    key.extend_from_slice(activity_id.as_bytes());
+    key
+}
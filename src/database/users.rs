@@ -1,7 +1,11 @@
-use crate::error::{Error, ErrorKind, Result};
+use crate::{
+    analysis::fitness::Thresholds,
+    error::{Error, ErrorKind, Result},
+};
 use argon2::{hash_encoded, verify_encoded, Config};
 use getrandom::getrandom;
 use std::convert::TryInto;
+use uom::si::{f64::Mass, mass::kilogram};
 
 #[derive(Clone)]
 pub struct UserTree {
@@ -9,6 +13,8 @@ pub struct UserTree {
     pub(super) username_standardgear: sled::Tree,
     pub(super) username_heartraterest: sled::Tree,
     pub(super) username_heartratemax: sled::Tree,
+    pub(super) username_ftp: sled::Tree,
+    pub(super) username_weight: sled::Tree,
 }
 
 impl UserTree {
@@ -81,6 +87,55 @@ impl UserTree {
         }
     }
 
+    pub fn set_ftp(&self, username: &str, ftp: Option<u16>) -> Result<()> {
+        match ftp {
+            Some(x) => self.username_ftp.insert(username, &x.to_ne_bytes())?,
+            None => self.username_ftp.remove(username)?,
+        };
+
+        Ok(())
+    }
+
+    pub fn get_ftp(&self, username: &str) -> Result<Option<u16>> {
+        match self.username_ftp.get(username)? {
+            Some(x) => {
+                Ok(Some(u16::from_ne_bytes(x.as_ref().try_into().map_err(
+                    |_| Error::BadServerResponse("Failed to get FTP"),
+                )?)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    pub fn set_weight(&self, username: &str, weight: Option<Mass>) -> Result<()> {
+        match weight {
+            Some(x) => self
+                .username_weight
+                .insert(username, &x.get::<kilogram>().to_ne_bytes())?,
+            None => self.username_weight.remove(username)?,
+        };
+
+        Ok(())
+    }
+
+    pub fn get_weight(&self, username: &str) -> Result<Option<Mass>> {
+        match self.username_weight.get(username)? {
+            Some(x) => Ok(Some(Mass::new::<kilogram>(f64::from_ne_bytes(
+                x.as_ref()
+                    .try_into()
+                    .map_err(|_| Error::BadServerResponse("Failed to get body weight"))?,
+            )))),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_thresholds(&self, username: &str) -> Result<Thresholds> {
+        Ok(Thresholds {
+            heartrate: self.get_heartrate(username)?,
+            ftp: self.get_ftp(username)?,
+        })
+    }
+
     pub fn verify_hash(&self, id: &str, password: &str) -> Result<bool> {
         let hash = String::from_utf8(
             self.username_password
@@ -0,0 +1,133 @@
+use crate::{
+    analysis::{self, elevation::Dem},
+    database::Database,
+    error::{Error, Result},
+    routes::utils,
+    tiles::TileSource,
+};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+const WORKERS: usize = 2;
+
+/// Work which is too slow to do during a request, queued in the database
+/// and done by the workers in order.
+#[derive(Serialize, Deserialize)]
+pub enum Job {
+    /// Computes the values derived from the record of an activity, and
+    /// updates everything depending on them
+    Analyze { username: String, id: String },
+    /// Renders the thumbnail of an activity shown on the front page, if its
+    /// track changed since the last one
+    Thumbnail { username: String, id: String },
+    /// Queues the analysis of every activity of a user
+    Reanalyze { username: String },
+    /// Matches a new segment against the activities of every user. Runs as
+    /// a job of the user who added it
+    MatchSegment { username: String, id: u64 },
+}
+
+impl Job {
+    /// The user the job runs for. Jobs of a user run one at a time.
+    pub fn username(&self) -> &str {
+        match self {
+            Job::Analyze { username, .. }
+            | Job::Thumbnail { username, .. }
+            | Job::Reanalyze { username }
+            | Job::MatchSegment { username, .. } => username,
+        }
+    }
+}
+
+/// Starts the workers, after queueing the jobs which were interrupted by a
+/// shutdown again.
+pub fn spawn_workers(data: &Database, tiles: &TileSource, dem: &Option<Dem>) {
+    data.jobs
+        .requeue_running()
+        .expect("Failed to requeue interrupted jobs");
+
+    for _ in 0..WORKERS {
+        let (data, tiles, dem) = (data.clone(), tiles.clone(), dem.clone());
+
+        std::thread::spawn(move || loop {
+            match data.jobs.take(Duration::from_secs(60)) {
+                Ok(Some((key, job))) => {
+                    // A job which can't be finished is queued again on the
+                    // next start
+                    let _ = match run(&data, &tiles, dem.as_ref(), &job) {
+                        Ok(()) => data.jobs.finish(&key, &job),
+                        Err(x) => data.jobs.fail(&key, &job, &x.to_string()),
+                    };
+                }
+                Ok(None) => (),
+                Err(_) => std::thread::sleep(Duration::from_secs(1)),
+            }
+        });
+    }
+}
+
+fn run(data: &Database, tiles: &TileSource, dem: Option<&Dem>, job: &Job) -> Result<()> {
+    match job {
+        Job::Analyze { username, id } => {
+            // The activity may have been deleted since the job was queued
+            if !data.activities.exists(username, id)? {
+                return Ok(());
+            }
+
+            let mut activity = data.activities.get_activity(username, id)?;
+            analysis::analyze(&mut activity, data.users.get_weight(username)?, dem);
+            utils::update_analysis(data, username, &activity)
+        }
+        Job::Thumbnail { username, id } => {
+            if !data.activities.exists(username, id)? {
+                return Ok(());
+            }
+
+            let record = data.activities.get_record(username, id)?;
+            let hash = match analysis::thumbnail::hash(&record) {
+                Some(x) => x,
+                None => return data.activities.remove_thumbnail(username, id),
+            };
+
+            if data
+                .activities
+                .get_thumbnail(username, id)?
+                .is_some_and(|x| x.0 == hash)
+            {
+                return Ok(());
+            }
+
+            // The plain thumbnail is kept until the track changes, if no
+            // tiles could be fetched
+            let image = match tiles.thumbnails {
+                true => {
+                    analysis::thumbnail::render_on_tiles(&record, |z, x, y| tiles.get(z, x, y).ok())
+                }
+                false => None,
+            }
+            .or_else(|| analysis::thumbnail::render(&record))
+            .ok_or(Error::BadServerResponse(
+                "Failed to render activity thumbnail",
+            ))?;
+            data.activities.set_thumbnail(username, id, hash, &image)
+        }
+        Job::Reanalyze { username } => {
+            for id in data.activities.username_iter_id(username)? {
+                data.jobs.push(&Job::Analyze {
+                    username: username.clone(),
+                    id,
+                })?;
+            }
+
+            Ok(())
+        }
+        Job::MatchSegment { id, .. } => {
+            // The segment may have been deleted since the job was queued
+            if !data.segments.exists(*id)? {
+                return Ok(());
+            }
+
+            data.segments.match_segment(*id, &data.activities)
+        }
+    }
+}
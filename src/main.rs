@@ -1,11 +1,14 @@
+mod analysis;
 mod config;
 mod database;
 mod error;
+mod jobs;
 mod middleware;
 mod models;
 mod parser;
 mod routes;
 mod static_files;
+mod tiles;
 
 #[cfg(all(target_env = "musl", target_pointer_width = "64"))]
 #[global_allocator]
@@ -24,19 +27,29 @@ async fn main() -> std::io::Result<()> {
     let data = Database::load_or_create().expect("Failed to load");
 
     let config = config::config();
-    let (cookie_key, secure_cookies, disable_registration, units) = (
+    let (cookie_key, secure_cookies, disable_registration, units, dem) = (
         config.get_cookie_key(),
         config.secure_cookies,
         config.disable_registration,
         config.get_units(),
+        config
+            .dem_directory
+            .clone()
+            .map(analysis::elevation::Dem::new),
     );
 
+    let tiles = tiles::TileSource::new(&config);
+
     println!("Running at {}:{}", config.address, config.port);
 
-    HttpServer::new(move || {
+    let (workers_data, workers_tiles, workers_dem) = (data.clone(), tiles.clone(), dem.clone());
+
+    let server = HttpServer::new(move || {
         App::new()
             .data(data.clone())
             .data(units.clone())
+            .data(dem.clone())
+            .data(tiles.clone())
             .wrap(Compress::default())
             .wrap(Condition::new(
                 disable_registration,
@@ -61,17 +74,25 @@ async fn main() -> std::io::Result<()> {
                     .wrap(middleware::CheckLogin::new(
                         middleware::AuthType::Restricted,
                     ))
+                    .configure(tiles::config)
                     .configure(routes::index::config)
                     .configure(routes::upload::config)
+                    .configure(routes::segment::config)
                     .service(
                         web::scope("user")
                             .configure(routes::activity::config)
+                            .configure(routes::compare::config)
+                            .configure(routes::photo::config)
                             .configure(routes::user::config)
-                            .configure(routes::gear::config),
+                            .configure(routes::gear::config)
+                            .configure(routes::route::config)
+                            .configure(routes::heatmap::config),
                     ),
             )
     })
-    .bind((config.address, config.port))?
-    .run()
-    .await
+    .bind((config.address, config.port))?;
+
+    jobs::spawn_workers(&workers_data, &workers_tiles, &workers_dem);
+
+    server.run().await
 }
@@ -2,7 +2,7 @@ use std::task::{Context, Poll};
 
 use actix_identity::Identity;
 use actix_web::dev::{Payload, Service, ServiceRequest, ServiceResponse, Transform};
-use actix_web::{http, Error, FromRequest, HttpRequest, HttpResponse, ResponseError};
+use actix_web::{http, Error, FromRequest, HttpRequest, HttpResponse};
 use futures::future::{ok, Either, Future, Ready};
 use std::pin::Pin;
 
@@ -24,6 +24,7 @@ impl<S, B> Transform<S> for Restricted
 where
     S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
     S::Future: 'static,
+    B: 'static,
 {
     type Request = ServiceRequest;
     type Response = ServiceResponse<B>;
@@ -45,6 +46,7 @@ impl<S, B> Service for RestrictedMiddleware<S>
 where
     S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
     S::Future: 'static,
+    B: 'static,
 {
     type Request = ServiceRequest;
     type Response = ServiceResponse<B>;
@@ -61,25 +63,19 @@ where
         let token = auto_login(&r, &mut pl);
         let req = ServiceRequest::from_parts(r, pl).ok().unwrap();
 
-        let fut = self.service.call(req);
-
-        Box::pin(async move {
-            let res = fut.await?;
-            let owner = res.request().match_info().query("username");
-            if owner == token.unwrap() {
-                Ok(res)
-            } else {
-                let new_body = crate::error::Error::BadRequest(
-                    crate::error::ErrorKind::Forbidden,
-                    "User is not authorized to view the requested route",
-                )
-                .error_response()
-                .into_body();
-                let res = res.into_response(new_body);
-
-                Ok(res)
-            }
-        })
+        // The owner is checked before the request is handled, so nothing is
+        // changed for other users
+        let owner = req.match_info().query("username");
+        if token.as_deref() != Some(owner) {
+            let res = req.error_response(crate::error::Error::BadRequest(
+                crate::error::ErrorKind::Forbidden,
+                "User is not authorized to view the requested route",
+            ));
+
+            return Box::pin(ok(res));
+        }
+
+        Box::pin(self.service.call(req))
     }
 }
 
@@ -1,21 +1,27 @@
 use super::{Duration, TimeStamp};
 use crate::error::{Error, Result};
+use chrono::Timelike;
 use serde::{Deserialize, Serialize};
 use std::str::FromStr;
 
 use uom::si::f64::{Length as Length_f64, Velocity};
 use uom::si::u16::Length as Length_u16;
 
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Activity {
     pub id: String,
     pub gear_id: Option<String>,
     pub session: Session,
     pub record: Record,
     pub lap: Vec<Lap>,
+    pub curve: Curve,
+    pub efforts: Vec<BestEffort>,
+    /// Description, in Markdown
     pub notes: Option<String>,
+    pub title: Option<String>,
 }
 
-#[derive(Default, Serialize, Deserialize)]
+#[derive(Default, Serialize, Deserialize, Clone)]
 #[serde(default)]
 pub struct Session {
     pub cadence_avg: Option<u8>,
@@ -39,9 +45,35 @@ pub struct Session {
     pub duration: Duration,
     pub duration_active: Duration,
     pub start_time: TimeStamp,
+    /// Ascent and descent from the digital elevation model, if one is
+    /// configured and covers the track. Stored sessions are arrays, so
+    /// fields added later need a default of their own.
+    #[serde(default)]
+    pub dem_elevation: Option<(Length_f64, Length_f64)>,
 }
 
-#[derive(Default, Serialize, Deserialize)]
+impl Session {
+    /// Title of activities which were not given one, from the time of day
+    /// and the activity type, like "Morning run".
+    pub fn default_title(&self) -> String {
+        let time = match self.start_time.0.hour() {
+            5..=11 => "Morning",
+            12..=16 => "Afternoon",
+            17..=20 => "Evening",
+            _ => "Night",
+        };
+        let activity = match &self.activity_type {
+            ActivityType::Running => "run".to_string(),
+            ActivityType::Cycling => "ride".to_string(),
+            x if *x == ActivityType::default() => "activity".to_string(),
+            x => x.to_string().to_lowercase(),
+        };
+
+        format!("{} {}", time, activity)
+    }
+}
+
+#[derive(Default, Serialize, Deserialize, Clone)]
 #[serde(default)]
 pub struct Record {
     pub cadence: Vec<Option<u8>>,
@@ -54,9 +86,13 @@ pub struct Record {
     pub lon: Vec<Option<f64>>,
     pub timestamp: Vec<TimeStamp>,
     pub duration: Vec<Duration>,
+    /// Temperature in degrees Celsius, which records stored before it was
+    /// added lack.
+    #[serde(default)]
+    pub temperature: Vec<Option<i8>>,
 }
 
-#[derive(Default, Serialize, Deserialize)]
+#[derive(Default, Serialize, Deserialize, Clone)]
 #[serde(default)]
 pub struct Lap {
     pub cadence_avg: Option<u8>,
@@ -79,6 +115,186 @@ pub struct Lap {
     pub duration_active: Duration,
 }
 
+/// Summary of the track cleaning applied to an activity. The raw record is
+/// stored separately, so that the cleaning can be undone.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Cleaning {
+    pub outliers: usize,
+    pub pauses: Vec<(Duration, Duration)>,
+    pub smoothed: bool,
+    pub distance: Option<Length_f64>,
+}
+
+impl Cleaning {
+    /// Whether the cleaning found nothing, and left the record as it was.
+    pub fn is_empty(&self) -> bool {
+        self.outliers == 0 && self.pauses.is_empty() && !self.smoothed
+    }
+}
+
+/// An edit of the record of one or more activities. The activities are kept
+/// as they were before, so that the edit can be undone.
+#[derive(Serialize, Deserialize)]
+pub struct Edit {
+    pub kind: EditKind,
+    /// Ids of the activities the edit created, removed when it is undone
+    pub created: Vec<String>,
+    pub originals: Vec<Original>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub enum EditKind {
+    Crop,
+    Split,
+    Merge,
+}
+
+impl std::fmt::Display for EditKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = match self {
+            Self::Crop => "crop",
+            Self::Split => "split",
+            Self::Merge => "merge",
+        };
+        write!(f, "{}", kind)
+    }
+}
+
+/// An activity as it was before an edit, with its raw record.
+#[derive(Serialize, Deserialize)]
+pub struct Original {
+    pub activity: Activity,
+    /// Whether the track was cleaned, and if so smoothed
+    pub smoothed: Option<bool>,
+    /// Edits of the activity, if it is not the one the edit is stored with
+    pub edits: Vec<Edit>,
+}
+
+/// Mean-maximal curves, where the value at index `i` is the best
+/// average sustained over the `i`th of `curve::durations`.
+#[derive(Default, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Curve {
+    pub power: Vec<u16>,
+    pub heartrate: Vec<u8>,
+    pub speed: Vec<Velocity>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum Effort {
+    Meters400,
+    Kilometer1,
+    Mile1,
+    Kilometers5,
+    Kilometers10,
+    HalfMarathon,
+    Marathon,
+    Power5Min,
+    Power20Min,
+    Power60Min,
+}
+
+impl Effort {
+    pub const ALL: [Effort; 10] = [
+        Self::Meters400,
+        Self::Kilometer1,
+        Self::Mile1,
+        Self::Kilometers5,
+        Self::Kilometers10,
+        Self::HalfMarathon,
+        Self::Marathon,
+        Self::Power5Min,
+        Self::Power20Min,
+        Self::Power60Min,
+    ];
+
+    /// Distance in meters, for efforts measured by time over a distance
+    pub const fn distance(&self) -> Option<f64> {
+        match self {
+            Self::Meters400 => Some(400.),
+            Self::Kilometer1 => Some(1000.),
+            Self::Mile1 => Some(1609.344),
+            Self::Kilometers5 => Some(5000.),
+            Self::Kilometers10 => Some(10000.),
+            Self::HalfMarathon => Some(21097.5),
+            Self::Marathon => Some(42195.),
+            _ => None,
+        }
+    }
+
+    /// Duration in seconds, for efforts measured by power over a duration
+    pub const fn duration(&self) -> Option<usize> {
+        match self {
+            Self::Power5Min => Some(5 * 60),
+            Self::Power20Min => Some(20 * 60),
+            Self::Power60Min => Some(60 * 60),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Effort {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let effort = match self {
+            Self::Meters400 => "400 m",
+            Self::Kilometer1 => "1 km",
+            Self::Mile1 => "1 mile",
+            Self::Kilometers5 => "5 km",
+            Self::Kilometers10 => "10 km",
+            Self::HalfMarathon => "Half marathon",
+            Self::Marathon => "Marathon",
+            Self::Power5Min => "5 min power",
+            Self::Power20Min => "20 min power",
+            Self::Power60Min => "60 min power",
+        };
+        write!(f, "{}", effort)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub enum EffortValue {
+    Time(Duration),
+    Power(u16),
+}
+
+impl EffortValue {
+    pub fn is_better_than(&self, other: &EffortValue) -> bool {
+        match (self, other) {
+            (Self::Time(x), Self::Time(y)) => x < y,
+            (Self::Power(x), Self::Power(y)) => x > y,
+            _ => false,
+        }
+    }
+}
+
+impl std::fmt::Display for EffortValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Time(x) => write!(f, "{}", x),
+            Self::Power(x) => write!(f, "{} W", x),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct BestEffort {
+    pub effort: Effort,
+    pub value: EffortValue,
+}
+
+/// A best effort which was the best of its kind when it was set.
+/// Records are superseded by later records of the same activity type and effort.
+#[derive(Serialize, Deserialize)]
+pub struct PersonalRecord {
+    pub activity_type: ActivityType,
+    pub effort: Effort,
+    pub value: EffortValue,
+    pub activity_id: String,
+    pub start_time: TimeStamp,
+    pub current: bool,
+}
+
 #[derive(Serialize, Deserialize, Clone, PartialOrd, Ord, PartialEq, Eq)]
 pub enum ActivityType {
     Running,
@@ -185,3 +401,44 @@ pub struct UserTotals {
     pub running_year: (Length_f64, Duration, usize),
     pub running_all: (Length_f64, Duration, usize),
 }
+
+/// A stretch of track, which activities of the same type are matched against.
+#[derive(Serialize, Deserialize)]
+pub struct Segment {
+    pub name: String,
+    pub username: String,
+    pub activity_type: ActivityType,
+    pub points: Vec<(f64, f64)>,
+    pub distance: Length_f64,
+}
+
+/// The fastest pass through a segment during an activity.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SegmentEffort {
+    pub start_time: TimeStamp,
+    pub duration: Duration,
+    pub power_avg: Option<u16>,
+    pub heartrate_avg: Option<u8>,
+}
+
+/// Activities of a user that follow the same course, keyed by the id of the
+/// first one. The simplified track of the first activity stands for the course.
+#[derive(Serialize, Deserialize)]
+pub struct Route {
+    pub name: Option<String>,
+    pub activity_type: ActivityType,
+    pub track: Vec<(f64, f64)>,
+    pub distance: Length_f64,
+    /// Ids of the activities on the route, oldest first
+    pub activity_ids: Vec<String>,
+}
+
+/// Photo attached to an activity, with what its EXIF data tells.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Photo {
+    pub content_type: String,
+    /// Time the photo was taken
+    pub taken: Option<TimeStamp>,
+    /// Position the photo was taken at, as (lat, lon)
+    pub position: Option<(f64, f64)>,
+}
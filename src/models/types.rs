@@ -1,6 +1,10 @@
+use crate::error::{Error, ErrorKind};
 use chrono::{offset::Local, DateTime};
 use serde::{Deserialize, Serialize};
-use std::ops::{Add, AddAssign, Sub};
+use std::{
+    ops::{Add, AddAssign, Sub},
+    str::FromStr,
+};
 use uom::si::{
     f64::{Length as Length_f64, Velocity},
     length::{foot, kilometer, meter, mile},
@@ -29,6 +33,27 @@ pub trait DisplayUnit {
     fn display_m_ft(&self, unit: &Unit) -> String;
 }
 
+pub trait DisplayPace {
+    fn display_pace(&self, unit: &Unit) -> String;
+}
+
+impl DisplayPace for Velocity {
+    fn display_pace(&self, unit: &Unit) -> String {
+        let length = match unit {
+            Unit::Metric => Length_f64::new::<kilometer>(1.),
+            Unit::Imperial => Length_f64::new::<mile>(1.),
+        };
+
+        let speed = self.get::<meter_per_second>();
+        if speed > 0. {
+            let s = (length.get::<meter>() / speed).round() as u64;
+            format!("{}:{:02} /{}", s / 60, s % 60, unit)
+        } else {
+            format!("-- /{}", unit)
+        }
+    }
+}
+
 pub trait GetWithUnit {
     fn get_with_unit(&self, unit: &Unit) -> f64;
 }
@@ -133,7 +158,7 @@ impl DisplayUnit for Length_u16 {
 }
 
 /// Wrapper for chrono::DateTime
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TimeStamp(pub DateTime<Local>);
 
 impl Default for TimeStamp {
@@ -157,6 +182,10 @@ impl Duration {
         Duration(std::time::Duration::from_secs_f64(secs))
     }
 
+    pub fn as_secs_f64(&self) -> f64 {
+        self.0.as_secs_f64()
+    }
+
     pub fn between(ts1: &TimeStamp, ts2: &TimeStamp) -> Self {
         Duration(
             chrono::Duration::to_std(&ts1.0.signed_duration_since(ts2.0))
@@ -201,3 +230,27 @@ impl std::fmt::Display for Duration {
         write!(f, "{:02}:{:02}:{:02}", h, m, s)
     }
 }
+
+impl FromStr for Duration {
+    type Err = Error;
+
+    /// Parses the format durations are displayed in, where hours and minutes
+    /// may be left out.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || Error::BadRequest(ErrorKind::BadRequest, "Failed to parse duration");
+        let parts: Vec<&str> = s.trim().split(':').collect();
+        if parts.len() > 3 {
+            return Err(error());
+        }
+
+        let mut seconds: u64 = 0;
+        for x in parts {
+            seconds = seconds
+                .checked_mul(60)
+                .and_then(|y| y.checked_add(x.parse().ok()?))
+                .ok_or_else(error)?;
+        }
+
+        Ok(Duration(std::time::Duration::from_secs(seconds)))
+    }
+}
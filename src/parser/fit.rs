@@ -3,7 +3,7 @@ use std::{collections::HashMap, str::FromStr};
 
 use crate::{
     error::{Error, ErrorKind, Result},
-    models::{Activity, ActivityType, Duration, Lap, Record, Session, TimeStamp},
+    models::{Activity, ActivityType, Curve, Duration, Lap, Record, Session, TimeStamp},
 };
 
 use uom::si::{
@@ -25,6 +25,7 @@ macro_rules! map_value {
 }
 
 map_value!(map_uint8, u8, Value::UInt8(x) => *x);
+map_value!(map_sint8, i8, Value::SInt8(x) => *x);
 map_value!(map_uint16, u16, Value::UInt16(x) => *x);
 map_value!(map_sint32, i32, Value::SInt32(x) => *x);
 map_value!(map_float64, f64, Value::Float64(x) => *x);
@@ -61,54 +62,18 @@ pub fn parse(fit_data: &[u8], gear_id: Option<String>) -> Result<Activity> {
         }
     }
 
-    // Some fit-files do not contain corner coordinates,
-    // so find them manually if missing
-    if session.nec_lat.is_none()
-        || session.nec_lon.is_none()
-        || session.swc_lat.is_none()
-        || session.swc_lon.is_none()
-    {
-        session.nec_lat = Some(
-            record
-                .lat
-                .iter()
-                .flatten()
-                .copied()
-                .fold(f64::NAN, f64::max),
-        );
-        session.nec_lon = Some(
-            record
-                .lon
-                .iter()
-                .flatten()
-                .copied()
-                .fold(f64::NAN, f64::max),
-        );
-        session.swc_lat = Some(
-            record
-                .lat
-                .iter()
-                .flatten()
-                .copied()
-                .fold(f64::NAN, f64::min),
-        );
-        session.swc_lon = Some(
-            record
-                .lon
-                .iter()
-                .flatten()
-                .copied()
-                .fold(f64::NAN, f64::min),
-        );
-    }
-
+    // Values missing from the session message are filled in from the
+    // record by the aggregation in the analysis
     Ok(Activity {
         id: session.start_time.0.format("%Y%m%d%H%M").to_string(),
         gear_id,
         session,
+        curve: Curve::default(),
+        efforts: Vec::new(),
         record,
         lap: lap_vec,
         notes: None,
+        title: None,
     })
 }
 
@@ -262,6 +227,12 @@ fn parse_record(fields: &[FitDataField], record: &mut Record) {
             .and_then(map_uint8)
     );
 
+    record.temperature.push(
+        field_map
+            .get("temperature")
+            .and_then(map_sint8)
+    );
+
     record.lat.push(
         field_map
             .get("position_lat")
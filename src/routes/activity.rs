@@ -1,16 +1,46 @@
 use super::{
-    api::{ActivityData, DataRequest, DataResponse},
+    api::{ActivityData, DataRequest, DataResponse, Series, SeriesQuery},
+    utils::TrackMetric,
     UrlActivity, UrlFor,
 };
 use crate::{
+    analysis::{
+        self,
+        climbs::{self, Climb},
+        edit,
+        elevation::{Dem, Elevation},
+        grade::GradeAdjusted,
+        photo,
+        splits::{self, Split},
+        track,
+    },
+    database::index::{Filter, Indexed},
+    error::{Error, ErrorKind},
+    jobs::Job,
     middleware::Restricted,
-    models::{ActivityType, DisplayUnit, Duration, Lap, Session, Unit},
+    models::{
+        Activity, ActivityType, BestEffort, Cleaning, DisplayPace, DisplayUnit, Duration, Edit,
+        EditKind, GetWithUnit, Lap, Original, Photo, Segment, SegmentEffort, Session, Unit,
+    },
 };
 use actix_identity::Identity;
 use actix_web::{http, web, HttpRequest, HttpResponse, Responder};
 use askama_actix::{Template, TemplateIntoResponse};
+use chrono::{Local, NaiveDate, TimeZone};
 use serde::Deserialize;
-use std::str::FromStr;
+use std::{collections::BTreeSet, str::FromStr};
+use uom::si::{
+    f64::{Length, Velocity},
+    length::{kilometer, mile},
+    velocity::meter_per_second,
+};
+
+// Amount of points in every series of the plot, unless requested otherwise
+const SERIES_RESOLUTION: usize = 2000;
+const MIN_SERIES_RESOLUTION: usize = 100;
+const MAX_SERIES_RESOLUTION: usize = 20000;
+// Longest break between two activities which can be merged, in seconds
+const MAX_MERGE_GAP: i64 = 24 * 60 * 60;
 
 pub fn config(cfg: &mut web::ServiceConfig) {
     cfg.service(
@@ -24,15 +54,57 @@ pub fn config(cfg: &mut web::ServiceConfig) {
             .name("activity")
             .to(activity),
     )
+    .service(
+        web::resource("/{username}/activity/{activity}/series")
+            .name("activity_series")
+            .to(activity_series),
+    )
+    .service(
+        web::resource("/{username}/activity/{activity}/thumbnail.png")
+            .name("activity_thumbnail")
+            .to(activity_thumbnail),
+    )
     .service(
         web::resource("/{username}/activity/{activity}/settings")
             .name("activity_settings")
             .wrap(Restricted)
             .route(web::get().to(activity_settings))
             .route(web::post().to(activity_settings_post)),
+    )
+    .service(
+        web::resource("/{username}/activity/{activity}/track")
+            .name("activity_track")
+            .wrap(Restricted)
+            .route(web::post().to(activity_track)),
+    )
+    .service(
+        web::resource("/{username}/activity/{activity}/edit")
+            .name("activity_edit")
+            .wrap(Restricted)
+            .route(web::post().to(activity_edit)),
+    )
+    .service(
+        web::resource("/{username}/activity/{activity}/segment")
+            .name("activity_segment")
+            .wrap(Restricted)
+            .route(web::post().to(super::segment::segment_add)),
+    )
+    .service(
+        web::resource("/{username}/activity/{activity}/delete")
+            .name("activity_delete")
+            .wrap(Restricted)
+            .route(web::post().to(activity_delete)),
     );
 }
 
+/// Photo of an activity, with where it is pinned on the map.
+struct PinnedPhoto {
+    id: String,
+    photo: Photo,
+    /// Coordinates as (lat, lon)
+    pin: Option<(f64, f64)>,
+}
+
 #[derive(Template)]
 #[template(path = "activity/activity.html")]
 struct ActivityTemplate<'a> {
@@ -46,10 +118,26 @@ struct ActivityTemplate<'a> {
     gear: Option<&'a str>,
     session: &'a Session,
     laps: &'a [Lap],
+    splits: &'a [Split],
+    elevation: &'a Elevation,
+    climbs: &'a [(Climb, Vec<(f64, f64)>)],
+    grade: Option<&'a GradeAdjusted>,
+    gradient_plot: Option<&'a str>,
+    cleaning: Option<&'a Cleaning>,
+    efforts: &'a [(BestEffort, bool)],
     coords: &'a [(f64, f64)],
+    coord_distances: &'a [f64],
+    coord_samples: &'a [usize],
+    track_metrics: &'a [TrackMetric],
+    lap_markers: &'a [(String, f64, f64)],
+    segments: &'a [(String, Segment, SegmentEffort, bool)],
     zones: Option<[Duration; 6]>,
-    notes: Option<&'a str>,
-    plot: &'a str,
+    photos: &'a [PinnedPhoto],
+    /// Description rendered from Markdown
+    description: Option<&'a str>,
+    analyzing: bool,
+    /// Whether the plot can be switched between distance and time
+    plot_distance: bool,
     title: &'a str,
 }
 
@@ -61,16 +149,137 @@ async fn activity(
     unit: web::Data<Unit>,
 ) -> impl Responder {
     let activity = data.activities.get_activity(&username, &activity_id)?;
+    let analyzing = data.jobs.is_pending(&Job::Analyze {
+        username: username.clone(),
+        id: activity_id.clone(),
+    })?;
+
+    let climbs = climbs::climbs(&activity.record);
+    let plot_distance = activity.record.distance.iter().any(Option::is_some);
+
+    // Coordinates of every climb, for highlighting it on the map
+    let climbs: Vec<(Climb, Vec<(f64, f64)>)> = climbs
+        .into_iter()
+        .map(|x| {
+            let coords = (x.start..=x.end)
+                .filter_map(|i| Some((activity.record.lat[i]?, activity.record.lon[i]?)))
+                .collect();
+            (x, coords)
+        })
+        .collect();
 
-    let plot = super::utils::plot(&activity.record, &unit)?;
+    let splits = splits::splits(&activity.record, &unit);
+    let elevation = Elevation::new(&activity.record, &activity.session);
+    let cleaning = data.activities.get_cleaning(&username, &activity_id)?;
+
+    let grade = match activity.session.activity_type {
+        ActivityType::Running => Some(GradeAdjusted::new(
+            &activity.record,
+            activity.session.speed_avg,
+            &activity.lap,
+        )),
+        _ => None,
+    };
+    // Activities entered without a file, or without altitude, have no gradient
+    let gradient_plot = grade
+        .as_ref()
+        .filter(|x| x.gradient.iter().any(Option::is_some))
+        .map(|x| super::utils::gradient_plot(&activity.record, x, &unit));
 
     let zones = {
         let user = data.users.get_heartrate(&username)?;
         super::utils::zone_duration(&activity.record, &user)
     };
 
-    let set: std::collections::BTreeSet<String> =
-        data.activities.username_iter_id(&username)?.collect();
+    // Flag the efforts which set a personal record at the time of the activity
+    let records = data.activities.get_personal_records(&username)?;
+    let efforts: Vec<(BestEffort, bool)> = activity
+        .efforts
+        .iter()
+        .map(|x| {
+            (
+                *x,
+                records
+                    .iter()
+                    .any(|y| y.activity_id == activity_id && y.effort == x.effort),
+            )
+        })
+        .collect();
+
+    // Segment efforts, flagged if they are the best of the user on the segment
+    let segments = data
+        .segments
+        .get_activity_efforts(&username, &activity_id)?
+        .into_iter()
+        .map(|(x, segment, effort)| {
+            let best = data
+                .segments
+                .get_efforts(x)?
+                .into_iter()
+                .find(|y| y.0 == username)
+                .is_some_and(|y| y.1 == activity_id);
+            let url = req.url_for("segment", &[x.to_string()])?.to_string();
+
+            Ok((url, segment, effort, best))
+        })
+        .collect::<actix_web::Result<Vec<(String, Segment, SegmentEffort, bool)>>>()?;
+
+    // Coordinates as (lon, lat), and the distance in km or mi at each of them
+    let (coords, coord_distances): (Vec<(f64, f64)>, Vec<f64>) = {
+        let mut distance = 0.;
+        let record = &activity.record;
+
+        (0..record.lat.len())
+            .filter_map(|i| {
+                if let Some(x) = record.distance[i] {
+                    distance = x.get_with_unit(&unit);
+                }
+                Some(((record.lon[i]?, record.lat[i]?), distance))
+            })
+            .unzip()
+    };
+
+    // Index in the record of every coordinate, for following the plot on the map
+    let coord_samples: Vec<usize> = (0..activity.record.lat.len())
+        .filter(|&i| activity.record.lat[i].is_some() && activity.record.lon[i].is_some())
+        .collect();
+    let track_metrics = super::utils::track_metrics(&activity.record, &unit);
+
+    // Start of every lap, and its end where the next lap does not start
+    let mut lap_markers = Vec::new();
+    for (i, lap) in activity.lap.iter().enumerate() {
+        if let (Some(lat), Some(lon)) = (lap.lat_start, lap.lon_start) {
+            lap_markers.push((format!("Lap {} start", i + 1), lat, lon));
+        }
+
+        let next = activity.lap.get(i + 1).map(|x| (x.lat_start, x.lon_start));
+        if let (Some(lat), Some(lon)) = (lap.lat_end, lap.lon_end) {
+            if next != Some((Some(lat), Some(lon))) {
+                lap_markers.push((format!("Lap {} end", i + 1), lat, lon));
+            }
+        }
+    }
+
+    // Photos in the order they were taken, those without a time last, and
+    // pinned to the track where possible
+    let mut photos: Vec<PinnedPhoto> = data
+        .photos
+        .iter(&username, &activity_id)?
+        .map(|(id, photo)| PinnedPhoto {
+            pin: photo::pin(&photo, &activity.record),
+            id,
+            photo,
+        })
+        .collect();
+    photos.sort_by_key(|x| {
+        (
+            x.photo.taken.is_none(),
+            x.photo.taken.as_ref().map(|y| y.0),
+            x.id.parse::<u64>().ok(),
+        )
+    });
+
+    let set: BTreeSet<String> = data.activities.username_iter_id(&username)?.collect();
 
     let prev = set
         .range(..activity_id.clone())
@@ -87,6 +296,8 @@ async fn activity(
         .map(|x| UrlActivity::new(&username, x, &req).ok())
         .flatten();
 
+    let description = activity.notes.as_deref().map(super::utils::markdown);
+
     ActivityTemplate {
         url: UrlFor::new(&id, &req)?,
         id,
@@ -98,17 +309,28 @@ async fn activity(
         gear: activity.gear_id.as_deref(),
         session: &activity.session,
         laps: &activity.lap,
-        coords: &activity
-            .record
-            .lon
-            .into_iter()
-            .flatten()
-            .zip(activity.record.lat.into_iter().flatten())
-            .collect::<Vec<(f64, f64)>>(),
+        splits: &splits,
+        elevation: &elevation,
+        climbs: &climbs,
+        grade: grade.as_ref(),
+        gradient_plot: gradient_plot.as_deref(),
+        cleaning: cleaning.as_ref(),
+        efforts: &efforts,
+        coords: &coords,
+        coord_distances: &coord_distances,
+        coord_samples: &coord_samples,
+        track_metrics: &track_metrics,
+        lap_markers: &lap_markers,
+        segments: &segments,
         zones,
-        plot: &plot,
-        notes: activity.notes.as_deref(),
-        title: &format!("Activity {}", &activity.session.start_time),
+        photos: &photos,
+        plot_distance,
+        description: description.as_deref(),
+        analyzing,
+        title: &activity
+            .title
+            .clone()
+            .unwrap_or_else(|| activity.session.default_title()),
     }
     .into_response()
 }
@@ -120,11 +342,42 @@ struct ActivitySettingsTemplate<'a> {
     id: Identity,
     activity_type: &'a ActivityType,
     gears: &'a [Option<String>],
+    activity_title: Option<&'a str>,
+    default_title: &'a str,
     notes: Option<&'a str>,
+    cleaning: Option<&'a Cleaning>,
+    editing: &'a Editing,
     title: &'a str,
     message: Option<&'a str>,
 }
 
+/// What the edit tools on the settings page offer.
+struct Editing {
+    duration: Duration,
+    /// The activity which can be merged into this one
+    next: Option<Session>,
+    undo: Option<EditKind>,
+}
+
+impl Editing {
+    fn new(data: &crate::Database, username: &str, activity: &Activity) -> Result<Self, Error> {
+        let next = match next_id(data, username, &activity.id)? {
+            Some(x) => Some(data.activities.get_session(username, &x)?),
+            None => None,
+        };
+
+        Ok(Self {
+            duration: activity.record.duration.last().copied().unwrap_or_default(),
+            next,
+            undo: data
+                .activities
+                .get_edits(username, &activity.id)?
+                .last()
+                .map(|x| x.kind),
+        })
+    }
+}
+
 async fn activity_settings(
     req: HttpRequest,
     id: Identity,
@@ -137,12 +390,19 @@ async fn activity_settings(
     gears.push(None);
     gears.sort_by_key(|k| k.as_ref() != activity.gear_id.as_ref());
 
+    let cleaning = data.activities.get_cleaning(&username, &activity_id)?;
+    let editing = Editing::new(&data, &username, &activity)?;
+
     ActivitySettingsTemplate {
         url: UrlFor::new(&id, &req)?,
         id,
         activity_type: &activity.session.activity_type,
         gears: &gears,
+        activity_title: activity.title.as_deref(),
+        default_title: &activity.session.default_title(),
         notes: activity.notes.as_deref(),
+        cleaning: cleaning.as_ref(),
+        editing: &editing,
         title: "Settings",
         message: None,
     }
@@ -151,6 +411,8 @@ async fn activity_settings(
 
 #[derive(Deserialize)]
 struct ActivitySettingsForm {
+    #[serde(default)]
+    pub title: String,
     pub activity_type: String,
     pub gear_id: Option<String>,
     pub notes: String,
@@ -184,12 +446,18 @@ async fn activity_settings_post(
         activity.session.activity_type =
             ActivityType::from_str(&form.activity_type).unwrap_or_default();
         activity.gear_id = gear_id;
+        activity.title = Some(form.title.trim().to_string()).filter(|x| !x.is_empty());
         activity.notes = match form.notes.is_empty() {
             true => None,
             false => Some(form.notes),
         };
 
-        data.activities.insert_or_overwrite(activity, &username)?;
+        // Segments and routes only match activities of the same type
+        data.segments.update_efforts(&username, &activity)?;
+        data.routes.assign(&username, &activity)?;
+        data.activities.set_details(&username, &activity)?;
+        data.activities
+            .update_personal_records(&username, &activity_id)?;
 
         let url: UrlActivity = UrlActivity::new(&username, &activity_id, &req)?;
 
@@ -199,24 +467,433 @@ async fn activity_settings_post(
             .into_body());
     }
 
+    let cleaning = data.activities.get_cleaning(&username, &activity_id)?;
+    let editing = Editing::new(&data, &username, &activity)?;
+
     ActivitySettingsTemplate {
         url: UrlFor::new(&id, &req)?,
         id,
         activity_type: &activity.session.activity_type,
         gears: &gears,
+        activity_title: activity.title.as_deref(),
+        default_title: &activity.session.default_title(),
         notes: activity.notes.as_deref(),
+        cleaning: cleaning.as_ref(),
+        editing: &editing,
         title: "Settings",
         message: result,
     }
     .into_response()
 }
 
+#[derive(Deserialize)]
+struct TrackForm {
+    pub action: String,
+    #[serde(default)]
+    pub smooth: bool,
+}
+
+async fn activity_track(
+    req: HttpRequest,
+    data: web::Data<crate::Database>,
+    dem: web::Data<Option<Dem>>,
+    web::Path((username, activity_id)): web::Path<(String, String)>,
+    form: web::Form<TrackForm>,
+) -> actix_web::Result<HttpResponse> {
+    let mut activity = data.activities.get_activity(&username, &activity_id)?;
+
+    // Always start from the raw record, so cleaning is never applied twice
+    if let Some((cleaning, raw)) = data.activities.take_raw(&username, &activity_id)? {
+        track::restore(&mut activity, cleaning, raw);
+    }
+
+    let weight = data.users.get_weight(&username)?;
+    let (data, owner) = (data.clone(), username.clone());
+    web::block(move || {
+        if form.action == "clean" {
+            let (cleaning, raw) = track::clean(&mut activity, form.smooth);
+            data.activities
+                .insert_raw(&owner, &activity.id, &cleaning, &raw)?;
+        }
+        analysis::analyze(&mut activity, weight, dem.as_ref().as_ref());
+
+        super::utils::update_activity(&data, &owner, activity)
+    })
+    .await?;
+
+    let url: UrlActivity = UrlActivity::new(&username, &activity_id, &req)?;
+
+    Ok(HttpResponse::Found()
+        .header(http::header::LOCATION, url.url.as_str())
+        .finish())
+}
+
+#[derive(Deserialize)]
+struct EditForm {
+    pub action: String,
+    #[serde(default)]
+    pub start: String,
+    #[serde(default)]
+    pub end: String,
+    #[serde(default)]
+    pub at: String,
+}
+
+/// Crops, splits or merges activities, or undoes the last of these edits.
+/// Edits apply to the raw track, which is cleaned again afterwards if it
+/// was cleaned before. Ids follow the start time, so cropping the start may
+/// move the activity to another id.
+async fn activity_edit(
+    req: HttpRequest,
+    data: web::Data<crate::Database>,
+    dem: web::Data<Option<Dem>>,
+    web::Path((username, activity_id)): web::Path<(String, String)>,
+    form: web::Form<EditForm>,
+) -> actix_web::Result<HttpResponse> {
+    let form = form.into_inner();
+    let weight = data.users.get_weight(&username)?;
+    let (mut activity, smoothed) = raw_activity(&data, &username, &activity_id)?;
+    let mut edits = data.activities.get_edits(&username, &activity_id)?;
+    let length = activity.record.duration.len();
+    let bad_request = |x| Error::BadRequest(ErrorKind::BadRequest, x);
+
+    // Activities to store along with their edits, and activities to remove
+    let mut stored = Vec::new();
+    let mut removed = Vec::new();
+
+    let smoothed = match form.action.as_str() {
+        "crop" => {
+            let start = match form.start.trim().is_empty() {
+                true => 0,
+                false => edit::sample_at(&activity.record, form.start.parse()?),
+            };
+            let end = match form.end.trim().is_empty() {
+                true => length,
+                false => edit::sample_at(&activity.record, form.end.parse()?),
+            };
+
+            if end < start + 2 {
+                return Err(bad_request("The cropped activity would be empty").into());
+            }
+            if start == 0 && end == length {
+                return Err(bad_request("Nothing would be cropped").into());
+            }
+
+            let original = activity.clone();
+            edit::crop(&mut activity, start..end, weight);
+
+            // Activities are keyed by their start, like the second part of a split
+            let id = activity
+                .session
+                .start_time
+                .0
+                .format("%Y%m%d%H%M")
+                .to_string();
+            let mut created = Vec::new();
+            if id != activity_id {
+                if data.activities.exists(&username, &id)? {
+                    return Err(
+                        bad_request("An activity starting with the cropped part exists").into(),
+                    );
+                }
+                activity.id = id.clone();
+                created.push(id);
+                removed.push(activity_id.clone());
+            }
+
+            edits.push(Edit {
+                kind: EditKind::Crop,
+                created,
+                originals: vec![Original {
+                    activity: original,
+                    smoothed,
+                    edits: Vec::new(),
+                }],
+            });
+
+            smoothed
+        }
+        "split" => {
+            let at = edit::sample_at(&activity.record, form.at.parse()?);
+            if at < 2 || at + 2 > length {
+                return Err(
+                    bad_request("The split point is too close to the start or the end").into(),
+                );
+            }
+
+            let original = activity.clone();
+            let second = edit::split(&mut activity, at, weight);
+            if data.activities.exists(&username, &second.id)? {
+                return Err(bad_request("An activity starting with the second part exists").into());
+            }
+
+            edits.push(Edit {
+                kind: EditKind::Split,
+                created: vec![second.id.clone()],
+                originals: vec![Original {
+                    activity: original,
+                    smoothed,
+                    edits: Vec::new(),
+                }],
+            });
+            stored.push((second, smoothed, Vec::new()));
+
+            smoothed
+        }
+        "merge" => {
+            let next_id = next_id(&data, &username, &activity_id)?
+                .ok_or_else(|| bad_request("There is no later activity to merge with"))?;
+            let (next, next_smoothed) = raw_activity(&data, &username, &next_id)?;
+
+            let gap = match (
+                activity.record.timestamp.last(),
+                next.record.timestamp.first(),
+            ) {
+                (Some(x), Some(y)) => y.0.signed_duration_since(x.0).num_seconds(),
+                _ => 0,
+            };
+            if gap < 0 {
+                return Err(bad_request("The next activity starts before this one ends").into());
+            }
+            if gap > MAX_MERGE_GAP {
+                return Err(bad_request("The next activity starts more than a day later").into());
+            }
+
+            edits.push(Edit {
+                kind: EditKind::Merge,
+                created: Vec::new(),
+                originals: vec![
+                    Original {
+                        activity: activity.clone(),
+                        smoothed,
+                        edits: Vec::new(),
+                    },
+                    Original {
+                        activity: next.clone(),
+                        smoothed: next_smoothed,
+                        edits: data.activities.get_edits(&username, &next_id)?,
+                    },
+                ],
+            });
+            edit::merge(&mut activity, next);
+            removed.push(next_id);
+
+            smoothed
+        }
+        "undo" => {
+            let edit = edits
+                .pop()
+                .ok_or_else(|| bad_request("There is no edit to undo"))?;
+
+            // Activities merged into this one, or the activity before a crop
+            // moved it, may have been uploaded again
+            for original in &edit.originals {
+                if original.activity.id != activity_id
+                    && data.activities.exists(&username, &original.activity.id)?
+                    && !edit.created.contains(&original.activity.id)
+                {
+                    return Err(bad_request("An activity with the same start time exists").into());
+                }
+            }
+            removed = edit.created;
+
+            // The first original is the activity which was edited
+            let mut originals = edit.originals.into_iter();
+            let original = originals
+                .next()
+                .ok_or(Error::BadServerResponse("Failed to undo the edit"))?;
+            for x in originals {
+                stored.push((x.activity, x.smoothed, x.edits));
+            }
+            activity = original.activity;
+
+            original.smoothed
+        }
+        _ => return Err(bad_request("Unknown edit").into()),
+    };
+    let edited = activity.id.clone();
+    let url: UrlActivity = UrlActivity::new(&username, &edited, &req)?;
+    stored.insert(0, (activity, smoothed, edits));
+
+    let (data, owner) = (data.clone(), username.clone());
+    web::block(move || -> Result<(), Error> {
+        for (mut activity, smoothed, edits) in stored {
+            data.activities.set_edits(&owner, &activity.id, &edits)?;
+
+            match smoothed {
+                Some(smooth) => {
+                    let (cleaning, raw) = track::clean(&mut activity, smooth);
+                    data.activities
+                        .insert_raw(&owner, &activity.id, &cleaning, &raw)?;
+                }
+                None => {
+                    data.activities.take_raw(&owner, &activity.id)?;
+                }
+            }
+            analysis::analyze(&mut activity, weight, dem.as_ref().as_ref());
+
+            super::utils::update_activity(&data, &owner, activity)?;
+        }
+
+        // Removed last, so nothing is lost if storing fails. Their photos
+        // are kept with the edited activity.
+        for id in &removed {
+            data.photos.move_activity(&owner, id, &edited)?;
+            if data.activities.exists(&owner, id)? {
+                remove_activity(&data, &owner, id)?;
+            }
+        }
+
+        Ok(())
+    })
+    .await?;
+
+    Ok(HttpResponse::Found()
+        .header(http::header::LOCATION, url.url.as_str())
+        .finish())
+}
+
+/// Gets an activity with its raw track, and whether the track was cleaned
+/// and smoothed.
+fn raw_activity(
+    data: &crate::Database,
+    username: &str,
+    id: &str,
+) -> Result<(Activity, Option<bool>), Error> {
+    let mut activity = data.activities.get_activity(username, id)?;
+
+    Ok(match data.activities.get_raw(username, id)? {
+        Some((cleaning, raw)) => {
+            let smoothed = cleaning.smoothed;
+            track::restore(&mut activity, cleaning, raw);
+            (activity, Some(smoothed))
+        }
+        None => (activity, None),
+    })
+}
+
+/// Id of the activity started next after the given one.
+fn next_id(data: &crate::Database, username: &str, id: &str) -> Result<Option<String>, Error> {
+    Ok(data
+        .activities
+        .username_iter_id(username)?
+        .filter(|x| x.as_str() > id)
+        .min())
+}
+
+/// Removes an activity, along with its segment efforts and routes.
+fn remove_activity(data: &crate::Database, username: &str, id: &str) -> Result<(), Error> {
+    data.activities.remove(username, id)?;
+    data.photos.remove_activity(username, id)?;
+    data.segments.remove_efforts(username, id)?;
+    data.routes.remove_activity(username, id)?;
+    super::heatmap::remove_cache(username);
+    data.activities.update_personal_records(username, id)
+}
+
+/// Serves the series of the plot of an activity as JSON. The ETag depends on
+/// the version of the record, so the series are only sent again when it changes.
+async fn activity_series(
+    req: HttpRequest,
+    data: web::Data<crate::Database>,
+    web::Path((username, activity_id)): web::Path<(String, String)>,
+    query: web::Query<SeriesQuery>,
+    unit: web::Data<Unit>,
+) -> actix_web::Result<HttpResponse> {
+    let resolution = query
+        .resolution
+        .unwrap_or(SERIES_RESOLUTION)
+        .clamp(MIN_SERIES_RESOLUTION, MAX_SERIES_RESOLUTION);
+
+    // The id is part of the ETag, as versions of records at different ids
+    // are counted separately
+    let version = data
+        .activities
+        .get_record_version(&username, &activity_id)?;
+    let unit_name = match **unit {
+        Unit::Metric => "metric",
+        Unit::Imperial => "imperial",
+    };
+    let etag = format!(
+        "\"{}-{}-{}-{}\"",
+        activity_id, version, resolution, unit_name
+    );
+
+    if req
+        .headers()
+        .get(http::header::IF_NONE_MATCH)
+        .and_then(|x| x.to_str().ok())
+        == Some(etag.as_str())
+    {
+        return Ok(HttpResponse::NotModified()
+            .header(http::header::ETAG, etag)
+            .finish());
+    }
+
+    let record = data.activities.get_record(&username, &activity_id)?;
+    let series = web::block(move || -> Result<Series, Error> {
+        let climbs = climbs::climbs(&record);
+        Ok(super::utils::series(&record, &climbs, &unit, resolution))
+    })
+    .await?;
+
+    Ok(HttpResponse::Ok()
+        .header(http::header::ETAG, etag)
+        .header(http::header::CACHE_CONTROL, "no-cache")
+        .json(series))
+}
+
+/// Serves the thumbnail of an activity. Links to it carry the hash of the
+/// track, so it can be cached for good.
+async fn activity_thumbnail(
+    data: web::Data<crate::Database>,
+    web::Path((username, activity_id)): web::Path<(String, String)>,
+) -> actix_web::Result<HttpResponse> {
+    let (_, image) = data
+        .activities
+        .get_thumbnail(&username, &activity_id)?
+        .ok_or(Error::BadRequest(
+            ErrorKind::NotFound,
+            "Thumbnail not found",
+        ))?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("image/png")
+        .header(
+            http::header::CACHE_CONTROL,
+            "private, max-age=31536000, immutable",
+        )
+        .body(image))
+}
+
+async fn activity_delete(
+    req: HttpRequest,
+    id: Identity,
+    data: web::Data<crate::Database>,
+    web::Path((username, activity_id)): web::Path<(String, String)>,
+) -> actix_web::Result<HttpResponse> {
+    if !data.activities.exists(&username, &activity_id)? {
+        return Err(Error::BadRequest(ErrorKind::NotFound, "Activity not found").into());
+    }
+
+    remove_activity(&data, &username, &activity_id)?;
+
+    let url: UrlFor = UrlFor::new(&id, &req)?;
+
+    Ok(HttpResponse::Found()
+        .header(http::header::LOCATION, url.activity_index.as_str())
+        .finish())
+}
+
 #[derive(Template)]
 #[template(path = "activity/index.html")]
 struct ActivityIndexTemplate<'a> {
     url: UrlFor,
     id: Identity,
+    unit: &'a Unit,
     username: &'a str,
+    activity_types: &'a BTreeSet<String>,
+    gears: &'a [String],
     title: &'a str,
 }
 
@@ -225,13 +902,20 @@ async fn activity_index(
     id: Identity,
     username: web::Path<String>,
     data: web::Data<crate::Database>,
+    unit: web::Data<Unit>,
 ) -> impl Responder {
     data.users.exists(&username)?;
 
+    let activity_types = data.activities.activity_types(&username)?;
+    let gears: Vec<String> = data.gear.iter(&username)?.map(|x| x.name).collect();
+
     ActivityIndexTemplate {
         url: UrlFor::new(&id, &req)?,
         id,
+        unit: &unit,
         username: &username,
+        activity_types: &activity_types,
+        gears: &gears,
         title: "Activities",
     }
     .into_response()
@@ -242,43 +926,107 @@ async fn activity_index_post(
     username: web::Path<String>,
     data: web::Data<crate::Database>,
     unit: web::Data<Unit>,
-) -> impl Responder {
-    let mut sessions: Vec<(Session, Option<String>)> = data
-        .activities
-        .username_iter_session(&username)
-        .unwrap()
-        .zip(data.activities.username_iter_gear(&username).unwrap())
-        .collect();
+) -> Result<web::Json<DataResponse<ActivityData>>, Error> {
+    let filter = list_filter(&request, &unit)?;
 
-    let amount = sessions.len();
-
-    match request.column {
-        0 => sessions.sort_by_key(|(k, _)| std::cmp::Reverse(k.start_time.0)),
-        1 => sessions.sort_by_key(|(k, _)| k.activity_type.to_owned()),
-        2 => sessions
-            .sort_by(|(a, _), (b, _)| a.duration_active.partial_cmp(&b.duration_active).unwrap()),
-        3 => sessions.sort_by(|(a, _), (b, _)| a.distance.partial_cmp(&b.distance).unwrap()),
-        4 => sessions.sort_by_key(|(k, _)| k.calories),
-        5 => sessions.sort_by_key(|(k, _)| k.cadence_avg),
-        6 => sessions.sort_by_key(|(k, _)| k.heartrate_avg),
-        7 => sessions.sort_by_key(|(k, _)| k.heartrate_max),
-        8 => sessions.sort_by(|(a, _), (b, _)| a.speed_avg.partial_cmp(&b.speed_avg).unwrap()),
-        9 => sessions.sort_by(|(a, _), (b, _)| a.speed_max.partial_cmp(&b.speed_max).unwrap()),
-        10 => sessions.sort_by_key(|(k, _)| k.ascent),
-        11 => sessions.sort_by_key(|(k, _)| k.descent),
-        _ => (),
+    // Ids of the matching activities, newest first
+    let amount = data.activities.count(&username)?;
+    let ids: Vec<String> = match data.activities.filter(&username, &filter)? {
+        Some(x) => x.into_iter().rev().collect(),
+        None => data.activities.username_iter_id(&username)?.collect(),
     };
+    let filtered = ids.len();
 
-    if request.dir.as_str() == "asc" {
-        sessions.reverse();
-    }
+    // Other columns than the date show the highest values first when sorted
+    // ascending
+    let indexed = match request.column {
+        0 => Some(Indexed::Start),
+        3 => Some(Indexed::Duration),
+        4 => Some(Indexed::Distance),
+        _ => None,
+    };
+    let reverse = match request.column {
+        0 => request.dir.as_str() == "desc",
+        _ => request.dir.as_str() == "asc",
+    };
 
-    let results: Vec<ActivityData> = sessions
-        .iter()
-        .skip(request.start)
-        .take(request.length)
-        .map(|(x, gear)| ActivityData {
+    let activities = match indexed {
+        // Sorted by the index, so only the activities shown are read
+        Some(x) => {
+            let matching: BTreeSet<&String> = ids.iter().collect();
+            let mut sorted: Vec<String> = data
+                .activities
+                .sorted(&username, x)?
+                .into_iter()
+                .filter(|y| matching.contains(y))
+                .collect();
+
+            // Activities without the value are the lowest
+            let indexed: BTreeSet<&String> = sorted.iter().collect();
+            let mut missing: Vec<String> = ids
+                .iter()
+                .filter(|y| !indexed.contains(y))
+                .cloned()
+                .collect();
+            missing.append(&mut sorted);
+
+            if reverse {
+                missing.reverse();
+            }
+            missing
+                .into_iter()
+                .skip(request.start)
+                .take(request.length)
+                .map(|y| list_row(&data, &username, y))
+                .collect::<Result<Vec<_>, Error>>()?
+        }
+        // Only the values sorted by are read for every activity, the rest
+        // only for the activities shown
+        None => {
+            let session = |y: &str| data.activities.get_session(&username, y);
+            let speed = |x: Option<Velocity>| x.map(|y| y.get::<meter_per_second>());
+            // Missing speeds are the lowest, and NaN the highest
+            let speed_order = |a: &Option<f64>, b: &Option<f64>| match (a, b) {
+                (Some(a), Some(b)) => a.total_cmp(b),
+                _ => a.is_some().cmp(&b.is_some()),
+            };
+
+            let mut ids = match request.column {
+                1 => sort_ids(ids, |y| {
+                    let title = match data.activities.get_title(&username, y)? {
+                        Some(x) => x,
+                        None => session(y)?.default_title(),
+                    };
+                    Ok(title.to_lowercase())
+                }),
+                2 => sort_ids(ids, |y| Ok(session(y)?.activity_type.to_string())),
+                5 => sort_ids(ids, |y| Ok(session(y)?.calories)),
+                6 => sort_ids(ids, |y| Ok(session(y)?.cadence_avg)),
+                7 => sort_ids(ids, |y| Ok(session(y)?.heartrate_avg)),
+                8 => sort_ids(ids, |y| Ok(session(y)?.heartrate_max)),
+                9 => sort_ids_by(ids, |y| Ok(speed(session(y)?.speed_avg)), speed_order),
+                10 => sort_ids_by(ids, |y| Ok(speed(session(y)?.speed_max)), speed_order),
+                11 => sort_ids(ids, |y| Ok(session(y)?.ascent)),
+                12 => sort_ids(ids, |y| Ok(session(y)?.descent)),
+                _ => Ok(ids),
+            }?;
+
+            if reverse {
+                ids.reverse();
+            }
+            ids.into_iter()
+                .skip(request.start)
+                .take(request.length)
+                .map(|y| list_row(&data, &username, y))
+                .collect::<Result<Vec<_>, Error>>()?
+        }
+    };
+
+    let results: Vec<ActivityData> = activities
+        .into_iter()
+        .map(|(id, title, x, gear)| ActivityData {
             date: x.start_time.0,
+            title,
             activity_type: x.activity_type.to_string(),
             duration: x.duration_active.to_string(),
             distance: x.distance.map(|x| x.display_km_mi(&unit)),
@@ -290,15 +1038,103 @@ async fn activity_index_post(
             speed_max: x.speed_max.map(|x| x.display_km_mi(&unit)),
             ascent: x.ascent.map(|x| x.display_m_ft(&unit)),
             descent: x.descent.map(|x| x.display_m_ft(&unit)),
-            gear: gear.to_owned(),
-            id: x.start_time.0.format("%Y%m%d%H%M").to_string(),
+            gear,
+            id,
         })
         .collect();
 
-    web::Json(DataResponse {
+    Ok(web::Json(DataResponse {
         draw: request.draw,
         records_total: amount,
-        records_filtered: amount,
+        records_filtered: filtered,
         data: results,
+    }))
+}
+
+/// Sorts the ids of activities by a value read for each of them, keeping
+/// the order of activities with the same value.
+fn sort_ids<K: Ord>(
+    ids: Vec<String>,
+    key: impl Fn(&str) -> Result<K, Error>,
+) -> Result<Vec<String>, Error> {
+    sort_ids_by(ids, key, K::cmp)
+}
+
+fn sort_ids_by<K>(
+    ids: Vec<String>,
+    key: impl Fn(&str) -> Result<K, Error>,
+    compare: impl Fn(&K, &K) -> std::cmp::Ordering,
+) -> Result<Vec<String>, Error> {
+    let mut keyed = ids
+        .into_iter()
+        .map(|x| Ok((key(&x)?, x)))
+        .collect::<Result<Vec<(K, String)>, Error>>()?;
+    keyed.sort_by(|a, b| compare(&a.0, &b.0));
+
+    Ok(keyed.into_iter().map(|x| x.1).collect())
+}
+
+/// Id, title, session and gear of an activity in the list.
+fn list_row(
+    data: &crate::Database,
+    username: &str,
+    id: String,
+) -> Result<(String, String, Session, Option<String>), Error> {
+    let session = data.activities.get_session(username, &id)?;
+    let title = data
+        .activities
+        .get_title(username, &id)?
+        .unwrap_or_else(|| session.default_title());
+    let gear = data.activities.get_gear_id(username, &id)?;
+
+    Ok((id, title, session, gear))
+}
+
+/// Filter of the activity list, with distances in the unit of the user and
+/// dates in local time.
+fn list_filter(request: &DataRequest, unit: &Unit) -> Result<Filter, Error> {
+    let bad_request = |x| Error::BadRequest(ErrorKind::BadRequest, x);
+    let text = |x: &str| Some(x.trim().to_string()).filter(|y| !y.is_empty());
+
+    let date = |x: &str, end: bool| match text(x) {
+        Some(x) => NaiveDate::parse_from_str(&x, "%Y-%m-%d")
+            .ok()
+            .and_then(|y| match end {
+                true => y.and_hms_opt(23, 59, 59),
+                false => y.and_hms_opt(0, 0, 0),
+            })
+            .and_then(|y| Local.from_local_datetime(&y).earliest())
+            .map(Some)
+            .ok_or_else(|| bad_request("Invalid date in the filter")),
+        None => Ok(None),
+    };
+    let distance = |x: &str| -> Result<Option<Length>, Error> {
+        let value: f64 = match text(x) {
+            Some(x) => x
+                .parse()
+                .map_err(|_| bad_request("Invalid distance in the filter"))?,
+            None => return Ok(None),
+        };
+
+        Ok(Some(match unit {
+            Unit::Metric => Length::new::<kilometer>(value),
+            Unit::Imperial => Length::new::<mile>(value),
+        }))
+    };
+    let duration = |x: &str| text(x).map(|y| y.parse::<Duration>()).transpose();
+
+    let x = &request.filter;
+    Ok(Filter {
+        words: request
+            .search
+            .split(|y: char| !y.is_alphanumeric())
+            .filter(|y| !y.is_empty())
+            .map(str::to_lowercase)
+            .collect(),
+        activity_type: text(&x.activity_type),
+        gear: text(&x.gear),
+        start: (date(&x.date_from, false)?, date(&x.date_to, true)?),
+        distance: (distance(&x.distance_min)?, distance(&x.distance_max)?),
+        duration: (duration(&x.duration_min)?, duration(&x.duration_max)?),
     })
 }
@@ -8,6 +8,28 @@ pub struct DataRequest {
     pub length: usize,
     pub column: usize,
     pub dir: String,
+    /// Words searched for in the titles and descriptions
+    #[serde(default)]
+    pub search: String,
+    #[serde(default)]
+    pub filter: FilterRequest,
+}
+
+/// Filters of the activity list, as entered. Empty fields are left out.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct FilterRequest {
+    pub activity_type: String,
+    pub gear: String,
+    /// Dates as YYYY-MM-DD
+    pub date_from: String,
+    pub date_to: String,
+    /// Distances in km or mi
+    pub distance_min: String,
+    pub distance_max: String,
+    /// Durations as HH:MM:SS
+    pub duration_min: String,
+    pub duration_max: String,
 }
 
 #[derive(Serialize)]
@@ -24,6 +46,7 @@ pub struct DataResponse<T: Serialize> {
 pub struct ActivityData {
     #[serde(with = "date_format")]
     pub date: DateTime<Local>,
+    pub title: String,
     pub activity_type: String,
     pub duration: String,
     pub distance: Option<String>,
@@ -39,6 +62,41 @@ pub struct ActivityData {
     pub id: String,
 }
 
+#[derive(Deserialize)]
+pub struct SeriesQuery {
+    /// Maximum amount of points in every series
+    pub resolution: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub struct Series {
+    /// Whether the layout has distance on the x-axis, rather than time
+    pub by_distance: bool,
+    pub layout: plotly::Layout,
+    pub traces: Vec<Trace>,
+    pub climbs: Vec<ClimbSpan>,
+}
+
+/// A channel of the record, as the values at the samples kept after
+/// downsampling, along with the distance and the elapsed milliseconds at them.
+#[derive(Serialize)]
+pub struct Trace {
+    pub name: &'static str,
+    pub y_axis: String,
+    pub color: &'static str,
+    pub samples: Vec<usize>,
+    pub distance: Vec<Option<f64>>,
+    pub time: Vec<u64>,
+    pub values: Vec<f64>,
+}
+
+/// Start and end of a climb on either x-axis.
+#[derive(Serialize)]
+pub struct ClimbSpan {
+    pub distance: [Option<f64>; 2],
+    pub time: [u64; 2],
+}
+
 #[derive(Serialize, Debug)]
 pub struct UserData {
     pub name: String,
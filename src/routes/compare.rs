@@ -0,0 +1,155 @@
+use super::{utils::PLOT_COLORS, UrlActivity, UrlFor};
+use crate::{
+    error::{Error, ErrorKind},
+    models::{Duration, Session, Unit},
+};
+use actix_identity::Identity;
+use actix_web::{web, HttpRequest, Responder};
+use askama_actix::{Template, TemplateIntoResponse};
+
+const MAX_ACTIVITIES: usize = PLOT_COLORS.len();
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource("/{username}/compare")
+            .name("compare")
+            .to(compare),
+    );
+}
+
+struct Compared {
+    url: String,
+    color: &'static str,
+    session: Session,
+    /// Coordinates as (lat, lon)
+    track: Vec<(f64, f64)>,
+}
+
+/// Duration of a lap, or of the whole activity, in each of the compared
+/// activities, with the difference to the first one.
+struct Difference {
+    name: String,
+    durations: Vec<Option<(Duration, Option<String>)>>,
+}
+
+#[derive(Template)]
+#[template(path = "activity/compare.html")]
+struct CompareTemplate<'a> {
+    url: UrlFor,
+    id: Identity,
+    unit: &'a Unit,
+    activities: &'a [Compared],
+    differences: &'a [Difference],
+    title: &'a str,
+}
+
+/// Shows activities of a user overlaid on one map and one plot. The
+/// activities are given as `activities` in the query, either once for every
+/// activity or as a comma separated list.
+async fn compare(
+    req: HttpRequest,
+    id: Identity,
+    data: web::Data<crate::Database>,
+    unit: web::Data<Unit>,
+    username: web::Path<String>,
+) -> impl Responder {
+    let mut ids: Vec<&str> = Vec::new();
+    for (key, value) in req.query_string().split('&').filter_map(|x| {
+        let mut pair = x.splitn(2, '=');
+        Some((pair.next()?, pair.next()?))
+    }) {
+        if key == "activities" {
+            for x in value.split(',').filter(|x| !x.is_empty()) {
+                if !ids.contains(&x) {
+                    ids.push(x);
+                }
+            }
+        }
+    }
+
+    if ids.len() < 2 || ids.len() > MAX_ACTIVITIES {
+        return Err(Error::BadRequest(
+            ErrorKind::BadRequest,
+            "Select between two and six activities to compare",
+        )
+        .into());
+    }
+
+    let mut activities = Vec::new();
+    let mut laps = Vec::new();
+    for (i, activity_id) in ids.iter().enumerate() {
+        let record = data.activities.get_record(&username, activity_id)?;
+        activities.push(Compared {
+            url: UrlActivity::new(&username, activity_id, &req)?
+                .url
+                .to_string(),
+            color: PLOT_COLORS[i],
+            session: data.activities.get_session(&username, activity_id)?,
+            track: record
+                .lat
+                .iter()
+                .zip(record.lon.iter())
+                .filter_map(|x| Some(((*x.0)?, (*x.1)?)))
+                .collect(),
+        });
+        laps.push(data.activities.get_lap(&username, activity_id)?);
+    }
+
+    // Differences are to the first activity, and to nothing if it has no such lap
+    let difference = |durations: Vec<Option<Duration>>| {
+        durations
+            .iter()
+            .enumerate()
+            .map(|(i, x)| {
+                let x = (*x)?;
+                let difference = match (i, durations[0]) {
+                    (0, _) | (_, None) => None,
+                    (_, Some(first)) => {
+                        let seconds = x.as_secs_f64() - first.as_secs_f64();
+                        let sign = match seconds < 0. {
+                            true => "-",
+                            false => "+",
+                        };
+                        Some(format!(
+                            "{}{}",
+                            sign,
+                            Duration::from_secs_f64(seconds.abs())
+                        ))
+                    }
+                };
+                Some((x, difference))
+            })
+            .collect()
+    };
+
+    let mut differences = Vec::new();
+    for i in 0..laps.iter().map(Vec::len).max().unwrap_or(0) {
+        differences.push(Difference {
+            name: format!("Lap {}", i + 1),
+            durations: difference(
+                laps.iter()
+                    .map(|x| x.get(i).map(|y| y.duration_active))
+                    .collect(),
+            ),
+        });
+    }
+    differences.push(Difference {
+        name: "Total".to_string(),
+        durations: difference(
+            activities
+                .iter()
+                .map(|x| Some(x.session.duration_active))
+                .collect(),
+        ),
+    });
+
+    CompareTemplate {
+        url: UrlFor::new(&id, &req)?,
+        id,
+        unit: &unit,
+        activities: &activities,
+        differences: &differences,
+        title: "Compare activities",
+    }
+    .into_response()
+}
@@ -0,0 +1,290 @@
+use super::UrlFor;
+use crate::{
+    analysis::heatmap::{self, Fnv, Tile},
+    error::{Error, ErrorKind, Result},
+    models::{ActivityType, Record},
+};
+use actix_identity::Identity;
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use askama_actix::{Template, TemplateIntoResponse};
+use chrono::NaiveDate;
+use serde::Deserialize;
+use std::{
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Mutex,
+};
+
+const CACHE_DIRECTORY: &str = "cache/heatmap";
+// Most sets of activities with cached tiles, for every user
+const MAX_CACHED_SETS: usize = 8;
+// Most sets of activities kept in memory, for all users together
+const MAX_REMEMBERED_SETS: usize = 64;
+
+/// Bounds of a track as (north, east, south, west), in degrees.
+type Bounds = (f64, f64, f64, f64);
+
+/// Ids of activities along with the bounds of their tracks.
+type Activities = Vec<(String, Bounds)>;
+
+/// Activities matched by filters, by user and the hash of their ids, so
+/// tiles don't have to filter every activity again. The most recently
+/// matched are last.
+static MATCHED: Mutex<Vec<(String, u64, Activities)>> = Mutex::new(Vec::new());
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource("/{username}/heatmap")
+            .name("heatmap")
+            .to(heatmap),
+    )
+    .service(
+        web::resource("/{username}/heatmap/{activities}/{z}/{x}/{y}.png")
+            .name("heatmap_tile")
+            .to(heatmap_tile),
+    );
+}
+
+#[derive(Deserialize)]
+struct HeatmapQuery {
+    #[serde(default)]
+    activity_type: String,
+    #[serde(default)]
+    from: String,
+    #[serde(default)]
+    to: String,
+    #[serde(default)]
+    gear: String,
+}
+
+impl HeatmapQuery {
+    /// Ids of the activities of the user which pass the filters, with the
+    /// bounds of their tracks.
+    fn activities(&self, data: &crate::Database, username: &str) -> Result<Activities> {
+        let date = |x: &str| match x {
+            "" => Ok(None),
+            _ => NaiveDate::parse_from_str(x, "%Y-%m-%d")
+                .map(Some)
+                .map_err(|_| Error::BadRequest(ErrorKind::BadRequest, "Invalid date")),
+        };
+        let (from, to) = (date(&self.from)?, date(&self.to)?);
+        let activity_type = match self.activity_type.as_str() {
+            "" => None,
+            x => Some(ActivityType::from_str(x).unwrap_or_default()),
+        };
+
+        let mut activities = Vec::new();
+        for (id, session) in data
+            .activities
+            .username_iter_id(username)?
+            .zip(data.activities.username_iter_session(username)?)
+        {
+            let day = session.start_time.0.naive_local().date();
+            if activity_type
+                .as_ref()
+                .is_some_and(|x| x != &session.activity_type)
+                || from.is_some_and(|x| day < x)
+                || to.is_some_and(|x| day > x)
+            {
+                continue;
+            }
+
+            if !self.gear.is_empty()
+                && data.activities.get_gear_id(username, &id)?.as_ref() != Some(&self.gear)
+            {
+                continue;
+            }
+
+            if let (Some(north), Some(east), Some(south), Some(west)) = (
+                session.nec_lat,
+                session.nec_lon,
+                session.swc_lat,
+                session.swc_lon,
+            ) {
+                activities.push((id, (north, east, south, west)));
+            }
+        }
+
+        Ok(activities)
+    }
+}
+
+#[derive(Template)]
+#[template(path = "user/heatmap.html")]
+struct HeatmapTemplate<'a> {
+    url: UrlFor,
+    id: Identity,
+    username: &'a str,
+    gears: &'a [String],
+    query: &'a HeatmapQuery,
+    query_string: &'a str,
+    activities: &'a str,
+    bounds: Option<Bounds>,
+    title: &'a str,
+}
+
+async fn heatmap(
+    req: HttpRequest,
+    id: Identity,
+    data: web::Data<crate::Database>,
+    username: web::Path<String>,
+    query: web::Query<HeatmapQuery>,
+) -> impl Responder {
+    data.users.exists(&username)?;
+
+    let gears = data
+        .gear
+        .iter(&username)?
+        .map(|x| x.name)
+        .collect::<Vec<String>>();
+
+    // Fit the map to all activities which pass the filters
+    let activities = query.activities(&data, &username)?;
+    let bounds = activities
+        .iter()
+        .fold(None, |acc: Option<Bounds>, (_, x)| match acc {
+            Some(y) => Some((y.0.max(x.0), y.1.max(x.1), y.2.min(x.2), y.3.min(x.3))),
+            None => Some(*x),
+        });
+    let hash = remember(&username, activities);
+
+    HeatmapTemplate {
+        url: UrlFor::new(&id, &req)?,
+        id,
+        username: &username,
+        gears: &gears,
+        query: &query,
+        query_string: req.query_string(),
+        activities: &format!("{:016x}", hash),
+        bounds,
+        title: "Heatmap",
+    }
+    .into_response()
+}
+
+/// Renders a tile of the heatmap. Tiles are cached on disk, in a directory
+/// for every set of matching activities, which the heatmap names by the
+/// hash of their ids. Edited tracks remove the cache.
+async fn heatmap_tile(
+    data: web::Data<crate::Database>,
+    web::Path((username, hash, z, x, y)): web::Path<(String, String, u8, u32, u32)>,
+    query: web::Query<HeatmapQuery>,
+) -> actix_web::Result<HttpResponse> {
+    data.users.exists(&username)?;
+
+    let tile = Tile::new(z, x, y).ok_or(Error::BadRequest(
+        ErrorKind::NotFound,
+        "Tile does not exist",
+    ))?;
+    let hash = u64::from_str_radix(&hash, 16)
+        .map_err(|_| Error::BadRequest(ErrorKind::NotFound, "Tile does not exist"))?;
+
+    let tile_path = |hash: u64| {
+        PathBuf::from(CACHE_DIRECTORY)
+            .join(&username)
+            .join(format!("{:016x}", hash))
+            .join(z.to_string())
+            .join(x.to_string())
+            .join(format!("{}.png", y))
+    };
+    if let Ok(x) = std::fs::read(tile_path(hash)) {
+        return Ok(HttpResponse::Ok().content_type("image/png").body(x));
+    }
+
+    // Activities are only filtered again if they were forgotten, like after
+    // a restart or a change of their tracks, and may match differently then
+    let (hash, activities) = match recall(&username, hash) {
+        Some(x) => (hash, x),
+        None => {
+            let activities = query.activities(&data, &username)?;
+            (remember(&username, activities.clone()), activities)
+        }
+    };
+    let path = tile_path(hash);
+
+    let image = web::block(move || -> Result<Vec<u8>> {
+        let (north, east, south, west) = tile.bounds();
+        let records = activities
+            .iter()
+            .filter(|(_, x)| x.2 <= north && x.0 >= south && x.3 <= east && x.1 >= west)
+            .map(|(x, _)| data.activities.get_record(&username, x))
+            .collect::<Result<Vec<Record>>>()?;
+
+        let image = heatmap::render(&heatmap::density(tile, records.iter()));
+
+        // Filters matching nothing render empty tiles, which aren't worth a
+        // directory of their own
+        if activities.is_empty() {
+            return Ok(image);
+        }
+
+        let directory = path.ancestors().nth(3).unwrap();
+        if !directory.exists() {
+            remove_oldest(directory.parent().unwrap(), MAX_CACHED_SETS - 1);
+        }
+        // The tile is still served if the cache can't be written
+        if std::fs::create_dir_all(path.parent().unwrap()).is_ok() {
+            std::fs::write(&path, &image).ok();
+        }
+
+        Ok(image)
+    })
+    .await?;
+
+    Ok(HttpResponse::Ok().content_type("image/png").body(image))
+}
+
+/// Keeps the activities matched by filters in memory, and returns the hash
+/// of their ids they are found by.
+fn remember(username: &str, activities: Activities) -> u64 {
+    let mut hash = Fnv::default();
+    activities
+        .iter()
+        .map(|x| &x.0)
+        .collect::<Vec<&String>>()
+        .hash(&mut hash);
+    let hash = hash.finish();
+
+    let mut matched = MATCHED.lock().unwrap();
+    matched.retain(|x| x.0 != username || x.1 != hash);
+    if matched.len() >= MAX_REMEMBERED_SETS {
+        matched.remove(0);
+    }
+    matched.push((username.to_string(), hash, activities));
+
+    hash
+}
+
+fn recall(username: &str, hash: u64) -> Option<Activities> {
+    MATCHED
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|x| x.0 == username && x.1 == hash)
+        .map(|x| x.2.clone())
+}
+
+/// Removes the cached tiles of a user, which is needed whenever the track
+/// of an activity changes.
+pub fn remove_cache(username: &str) {
+    MATCHED.lock().unwrap().retain(|x| x.0 != username);
+    std::fs::remove_dir_all(PathBuf::from(CACHE_DIRECTORY).join(username)).ok();
+}
+
+/// Removes the least recently created directories of cached tiles, until at
+/// most `keep` are left.
+fn remove_oldest(directory: &Path, keep: usize) {
+    let mut entries = match std::fs::read_dir(directory) {
+        Ok(x) => x
+            .flatten()
+            .filter_map(|x| Some((x.metadata().ok()?.modified().ok()?, x.path())))
+            .collect::<Vec<_>>(),
+        Err(_) => return,
+    };
+    entries.sort_unstable();
+
+    for (_, path) in entries.iter().rev().skip(keep) {
+        std::fs::remove_dir_all(path).ok();
+    }
+}
@@ -1,5 +1,8 @@
 use super::{UrlActivity, UrlFor};
-use crate::models::{DisplayUnit, Session, Unit};
+use crate::{
+    jobs::Job,
+    models::{DisplayUnit, Session, Unit},
+};
 use actix_identity::Identity;
 use actix_web::{web, HttpRequest, Responder};
 use askama_actix::{Template, TemplateIntoResponse};
@@ -19,10 +22,14 @@ struct IndexTemplate<'a> {
 }
 
 struct TemplateData {
+    title: String,
     session: Session,
     url: UrlActivity,
     username: String,
-    id: String,
+    /// Hash of the track the thumbnail was rendered from, None if there is none yet
+    thumbnail: Option<u64>,
+    /// Whether a thumbnail is being rendered
+    rendering: bool,
 }
 
 async fn index(
@@ -44,26 +51,46 @@ async fn index(
         .flat_map(|(x, y)| data.activities.get_session(&x, &y))
         .collect();
 
-    for (username, id) in username_id.iter() {
-        let path = format!("static/img/activity/{}_{}.png", &username, &id);
-        let path = std::path::PathBuf::from(&path);
+    // Thumbnails are rendered in the background, and queued here for
+    // activities which have none yet
+    let mut thumbnails = Vec::new();
+    for ((username, id), session) in username_id.iter().zip(sessions.iter()) {
+        let thumbnail = data.activities.get_thumbnail(username, id)?.map(|x| x.0);
+        // Activities without a track get a sparkline of one of these
+        let drawable = session.nec_lat.is_some()
+            || session.ascent.is_some()
+            || session.heartrate_avg.is_some()
+            || session.power_avg.is_some();
 
-        if !path.exists() {
-            let record = data.activities.get_record(&username, &id)?;
-
-            web::block(move || super::utils::generate_thumb(record, &path)).await?;
+        let job = Job::Thumbnail {
+            username: username.clone(),
+            id: id.clone(),
+        };
+        let mut rendering = thumbnail.is_none() && data.jobs.is_pending(&job)?;
+        if drawable && thumbnail.is_none() && !rendering {
+            data.jobs.push(&job)?;
+            rendering = true;
         }
+        thumbnails.push((thumbnail, rendering));
     }
 
     // This is necessary because Askama does not allow zipping iterators inside a template
     let template_data: Vec<TemplateData> = sessions
         .into_iter()
         .zip(username_id)
-        .map(|(x, (y, z))| TemplateData {
+        .zip(thumbnails)
+        .map(|((x, (y, z)), (thumbnail, rendering))| TemplateData {
+            title: data
+                .activities
+                .get_title(&y, &z)
+                .ok()
+                .flatten()
+                .unwrap_or_else(|| x.default_title()),
             session: x,
             url: UrlActivity::new(&y, &z, &req).unwrap(),
             username: y,
-            id: z,
+            thumbnail,
+            rendering,
         })
         .collect();
 
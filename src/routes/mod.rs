@@ -5,8 +5,13 @@ use url::Url;
 pub mod activity;
 pub mod api;
 pub mod authentication;
+pub mod compare;
 pub mod gear;
+pub mod heatmap;
 pub mod index;
+pub mod photo;
+pub mod route;
+pub mod segment;
 pub mod upload;
 pub mod user;
 pub mod utils;
@@ -19,7 +24,9 @@ pub struct UrlFor {
     pub activity_index: Url,
     pub gear_index: Url,
     pub gear_add: Url,
+    pub segment_index: Url,
     pub upload: Url,
+    pub upload_manual: Url,
     pub signin: Url,
     pub signup: Url,
 }
@@ -46,7 +53,9 @@ impl UrlFor {
                 "gear_add",
                 &[&user.identity().unwrap_or_else(|| "None".to_string())],
             )?,
+            segment_index: req.url_for_static("segment_index")?,
             upload: req.url_for_static("upload")?,
+            upload_manual: req.url_for_static("upload_manual")?,
             signin: req.url_for_static("signin")?,
             signup: req.url_for_static("signup")?,
         })
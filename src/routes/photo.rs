@@ -0,0 +1,175 @@
+use super::UrlActivity;
+use crate::{
+    analysis::photo,
+    error::{Error, ErrorKind},
+    middleware::Restricted,
+};
+use actix_multipart::Multipart;
+use actix_web::{error::BlockingError, http, web, HttpRequest, HttpResponse};
+use futures::{StreamExt, TryStreamExt};
+
+// Largest photo which can be attached, in bytes
+const MAX_PHOTO_SIZE: usize = 20 * 1024 * 1024;
+// Largest amount of photos which can be attached at once, in bytes
+const MAX_UPLOAD_SIZE: usize = 100 * 1024 * 1024;
+// Most photos an activity can have
+const MAX_PHOTOS: usize = 50;
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource("/{username}/activity/{activity}/photo")
+            .name("activity_photo_add")
+            .wrap(Restricted)
+            .route(web::post().to(photo_add)),
+    )
+    .service(
+        web::resource("/{username}/activity/{activity}/photo/{photo}")
+            .name("activity_photo")
+            .to(photo_image),
+    )
+    .service(
+        web::resource("/{username}/activity/{activity}/photo/{photo}/thumbnail")
+            .name("activity_photo_thumbnail")
+            .to(photo_thumbnail),
+    )
+    .service(
+        web::resource("/{username}/activity/{activity}/photo/{photo}/delete")
+            .name("activity_photo_delete")
+            .wrap(Restricted)
+            .route(web::post().to(photo_delete)),
+    );
+}
+
+/// Attaches the photos in every field of the form to the activity.
+async fn photo_add(
+    req: HttpRequest,
+    data: web::Data<crate::Database>,
+    web::Path((username, activity_id)): web::Path<(String, String)>,
+    mut payload: Multipart,
+) -> actix_web::Result<HttpResponse> {
+    if !data.activities.exists(&username, &activity_id)? {
+        return Err(Error::BadRequest(ErrorKind::NotFound, "Activity not found").into());
+    }
+
+    let mut images: Vec<Vec<u8>> = Vec::new();
+    let mut size = 0;
+    while let Ok(Some(mut field)) = payload.try_next().await {
+        let mut image = Vec::new();
+        while let Some(chunk) = field.next().await {
+            let chunk = chunk.map_err(|_| {
+                Error::BadRequest(ErrorKind::BadRequest, "Failed to read the photo")
+            })?;
+            if image.len() + chunk.len() > MAX_PHOTO_SIZE {
+                return Err(Error::BadRequest(
+                    ErrorKind::BadRequest,
+                    "Photos can be at most 20 MB",
+                )
+                .into());
+            }
+            size += chunk.len();
+            if size > MAX_UPLOAD_SIZE {
+                return Err(Error::BadRequest(
+                    ErrorKind::BadRequest,
+                    "At most 100 MB of photos can be attached at once",
+                )
+                .into());
+            }
+            image.extend_from_slice(chunk.as_ref());
+        }
+
+        // Fields are empty if no file was chosen
+        if !image.is_empty() {
+            images.push(image);
+        }
+    }
+
+    if data.photos.iter(&username, &activity_id)?.count() + images.len() > MAX_PHOTOS {
+        return Err(Error::BadRequest(
+            ErrorKind::BadRequest,
+            "An activity can have at most 50 photos",
+        )
+        .into());
+    }
+
+    let (data, owner, activity) = (data.clone(), username.clone(), activity_id.clone());
+    web::block(move || -> Result<(), Error> {
+        // Every photo is read before any is stored
+        let photos = images
+            .iter()
+            .map(|x| photo::read(x))
+            .collect::<Option<Vec<_>>>()
+            .ok_or(Error::BadRequest(
+                ErrorKind::BadRequest,
+                "Photos have to be JPEG or PNG images of at most 64 megapixels",
+            ))?;
+
+        for ((photo, thumbnail), image) in photos.into_iter().zip(images.iter()) {
+            data.photos
+                .insert(&owner, &activity, &photo, image, &thumbnail)?;
+        }
+
+        Ok(())
+    })
+    .await
+    .map_err(|x| match x {
+        BlockingError::Error(x) => x,
+        BlockingError::Canceled => Error::BadServerResponse("Failed to read the photos"),
+    })?;
+
+    let url: UrlActivity = UrlActivity::new(&username, &activity_id, &req)?;
+
+    Ok(HttpResponse::Found()
+        .header(http::header::LOCATION, format!("{}#photos", url.url))
+        .finish())
+}
+
+/// Serves a photo as it was uploaded. Ids of photos are never reused, so it
+/// can be cached for good, though only by the browser as it needs a login.
+async fn photo_image(
+    data: web::Data<crate::Database>,
+    web::Path((username, activity_id, photo_id)): web::Path<(String, String, String)>,
+) -> actix_web::Result<HttpResponse> {
+    let photo = data.photos.get(&username, &activity_id, &photo_id)?;
+    let image = data.photos.get_image(&username, &activity_id, &photo_id)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(photo.content_type)
+        .header(
+            http::header::CACHE_CONTROL,
+            "private, max-age=31536000, immutable",
+        )
+        .body(image))
+}
+
+async fn photo_thumbnail(
+    data: web::Data<crate::Database>,
+    web::Path((username, activity_id, photo_id)): web::Path<(String, String, String)>,
+) -> actix_web::Result<HttpResponse> {
+    let image = data
+        .photos
+        .get_thumbnail(&username, &activity_id, &photo_id)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("image/jpeg")
+        .header(
+            http::header::CACHE_CONTROL,
+            "private, max-age=31536000, immutable",
+        )
+        .body(image))
+}
+
+async fn photo_delete(
+    req: HttpRequest,
+    data: web::Data<crate::Database>,
+    web::Path((username, activity_id, photo_id)): web::Path<(String, String, String)>,
+) -> actix_web::Result<HttpResponse> {
+    // Fails if there is no such photo
+    data.photos.get(&username, &activity_id, &photo_id)?;
+    data.photos.remove(&username, &activity_id, &photo_id)?;
+
+    let url: UrlActivity = UrlActivity::new(&username, &activity_id, &req)?;
+
+    Ok(HttpResponse::Found()
+        .header(http::header::LOCATION, format!("{}#photos", url.url))
+        .finish())
+}
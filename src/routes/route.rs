@@ -0,0 +1,226 @@
+use super::{utils, UrlActivity, UrlFor};
+use crate::{
+    middleware::Restricted,
+    models::{DisplayPace, DisplayUnit, Route, Session, Unit},
+};
+use actix_identity::Identity;
+use actix_web::{http, web, HttpRequest, HttpResponse, Responder};
+use askama_actix::{Template, TemplateIntoResponse};
+use serde::Deserialize;
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource("/{username}/route")
+            .name("route_index")
+            .to(route_index),
+    )
+    .service(
+        web::resource("/{username}/route/rebuild")
+            .name("route_rebuild")
+            .wrap(Restricted)
+            .route(web::post().to(route_rebuild)),
+    )
+    .service(
+        web::resource("/{username}/route/{route}")
+            .name("route")
+            .to(route),
+    )
+    .service(
+        web::resource("/{username}/route/{route}/rename")
+            .name("route_rename")
+            .wrap(Restricted)
+            .route(web::post().to(route_rename)),
+    );
+}
+
+/// Name of a route, defaulting to its type and the date of its first attempt.
+fn route_name(route: &Route, first: &Session) -> String {
+    route.name.clone().unwrap_or_else(|| {
+        format!(
+            "{} route from {}",
+            route.activity_type,
+            first.start_time.0.format("%Y-%m-%d")
+        )
+    })
+}
+
+struct RouteEntry<'a> {
+    id: String,
+    name: String,
+    route: &'a Route,
+    last: Session,
+    best: Session,
+}
+
+#[derive(Template)]
+#[template(path = "route/index.html")]
+struct RouteIndexTemplate<'a> {
+    url: UrlFor,
+    id: Identity,
+    unit: &'a Unit,
+    username: &'a str,
+    routes: &'a [RouteEntry<'a>],
+    title: &'a str,
+}
+
+async fn route_index(
+    req: HttpRequest,
+    id: Identity,
+    data: web::Data<crate::Database>,
+    unit: web::Data<Unit>,
+    username: web::Path<String>,
+) -> impl Responder {
+    data.users.exists(&username)?;
+
+    // A route needs at least two attempts to be worth showing
+    let routes = data
+        .routes
+        .iter(&username)?
+        .filter(|(_, x)| x.activity_ids.len() > 1)
+        .collect::<Vec<(String, Route)>>();
+
+    let mut entries = Vec::new();
+    for (route_id, route) in &routes {
+        let sessions = route
+            .activity_ids
+            .iter()
+            .map(|x| data.activities.get_session(&username, x))
+            .collect::<crate::error::Result<Vec<Session>>>()?;
+        let name = route_name(route, &sessions[0]);
+        let best = sessions
+            .iter()
+            .min_by(|a, b| {
+                a.duration_active
+                    .as_secs_f64()
+                    .partial_cmp(&b.duration_active.as_secs_f64())
+                    .unwrap()
+            })
+            .cloned()
+            .unwrap();
+
+        entries.push(RouteEntry {
+            id: route_id.clone(),
+            name,
+            route,
+            last: sessions.last().cloned().unwrap(),
+            best,
+        });
+    }
+    entries.sort_by_key(|x| std::cmp::Reverse(x.last.start_time.0));
+
+    RouteIndexTemplate {
+        url: UrlFor::new(&id, &req)?,
+        id,
+        unit: &unit,
+        username: &username,
+        routes: &entries,
+        title: "Routes",
+    }
+    .into_response()
+}
+
+#[derive(Template)]
+#[template(path = "route/route.html")]
+struct RouteTemplate<'a> {
+    url: UrlFor,
+    id: Identity,
+    unit: &'a Unit,
+    username: &'a str,
+    route_id: &'a str,
+    route: &'a Route,
+    attempts: &'a [(String, Session)],
+    compare_url: &'a str,
+    plot: &'a str,
+    title: &'a str,
+}
+
+async fn route(
+    req: HttpRequest,
+    id: Identity,
+    data: web::Data<crate::Database>,
+    unit: web::Data<Unit>,
+    web::Path((username, route_id)): web::Path<(String, String)>,
+) -> impl Responder {
+    let route = data.routes.get(&username, &route_id)?;
+
+    let mut attempts = Vec::new();
+    for activity_id in &route.activity_ids {
+        attempts.push((
+            UrlActivity::new(&username, activity_id, &req)?
+                .url
+                .to_string(),
+            data.activities.get_session(&username, activity_id)?,
+        ));
+    }
+
+    let sessions = attempts
+        .iter()
+        .map(|x| x.1.clone())
+        .collect::<Vec<Session>>();
+    let plot = utils::route_plot(&sessions);
+    let name = route_name(&route, &sessions[0]);
+
+    // Newest attempts first in the table
+    attempts.reverse();
+
+    RouteTemplate {
+        url: UrlFor::new(&id, &req)?,
+        id,
+        unit: &unit,
+        username: &username,
+        route_id: &route_id,
+        route: &route,
+        attempts: &attempts,
+        compare_url: req.url_for("compare", [&username])?.as_str(),
+        plot: &plot,
+        title: &name,
+    }
+    .into_response()
+}
+
+#[derive(Deserialize)]
+struct RenameForm {
+    name: String,
+}
+
+async fn route_rename(
+    req: HttpRequest,
+    data: web::Data<crate::Database>,
+    web::Path((username, route_id)): web::Path<(String, String)>,
+    form: web::Form<RenameForm>,
+) -> actix_web::Result<HttpResponse> {
+    let name = form.into_inner().name.trim().to_string();
+    data.routes.rename(
+        &username,
+        &route_id,
+        match name.is_empty() {
+            true => None,
+            false => Some(name),
+        },
+    )?;
+
+    let url = req.url_for("route", [&username, &route_id])?;
+
+    Ok(HttpResponse::Found()
+        .header(http::header::LOCATION, url.as_str())
+        .finish())
+}
+
+/// Groups all activities of the user into routes again, which also covers
+/// activities uploaded before routes existed.
+async fn route_rebuild(
+    req: HttpRequest,
+    data: web::Data<crate::Database>,
+    username: web::Path<String>,
+) -> actix_web::Result<HttpResponse> {
+    let url = req.url_for("route_index", [username.as_str()])?;
+
+    // Comparing every activity takes a while
+    let data = data.clone();
+    let username = username.into_inner();
+    web::block(move || data.routes.rebuild(&username, &data.activities)).await?;
+
+    Ok(HttpResponse::Found()
+        .header(http::header::LOCATION, url.as_str())
+        .finish())
+}
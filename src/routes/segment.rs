@@ -0,0 +1,232 @@
+use super::{UrlActivity, UrlFor};
+use crate::{
+    analysis::segments,
+    error::{Error, ErrorKind},
+    jobs::Job,
+    models::{DisplayPace, DisplayUnit, Segment, SegmentEffort, Unit},
+};
+use actix_identity::Identity;
+use actix_web::{error::UrlGenerationError, http, web, HttpRequest, HttpResponse, Responder};
+use askama_actix::{Template, TemplateIntoResponse};
+use serde::Deserialize;
+use std::collections::HashSet;
+use uom::si::{
+    f64::{Length, Time, Velocity},
+    length::{kilometer, mile},
+    time::second,
+};
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource("/segment")
+            .name("segment_index")
+            .to(segment_index),
+    )
+    .service(
+        web::resource("/segment/{segment}")
+            .name("segment")
+            .to(segment),
+    )
+    .service(
+        web::resource("/segment/{segment}/delete")
+            .name("segment_delete")
+            .route(web::post().to(segment_delete)),
+    );
+}
+
+#[derive(Template)]
+#[template(path = "segment/index.html")]
+struct SegmentIndexTemplate<'a> {
+    url: UrlFor,
+    id: Identity,
+    unit: &'a Unit,
+    segments: &'a [(u64, Segment, usize)],
+    title: &'a str,
+}
+
+async fn segment_index(
+    req: HttpRequest,
+    id: Identity,
+    data: web::Data<crate::Database>,
+    unit: web::Data<Unit>,
+) -> impl Responder {
+    let segments = data
+        .segments
+        .iter()
+        .map(|(x, y)| Ok((x, y, data.segments.get_efforts(x)?.len())))
+        .collect::<crate::error::Result<Vec<(u64, Segment, usize)>>>()?;
+
+    SegmentIndexTemplate {
+        url: UrlFor::new(&id, &req)?,
+        id,
+        unit: &unit,
+        segments: &segments,
+        title: "Segments",
+    }
+    .into_response()
+}
+
+struct LeaderboardEntry<'a> {
+    username: &'a str,
+    activity_url: String,
+    effort: &'a SegmentEffort,
+    speed: Velocity,
+}
+
+fn leaderboard_entry<'a>(
+    (username, activity_id, effort): &'a (String, String, SegmentEffort),
+    segment: &Segment,
+    req: &HttpRequest,
+) -> Result<LeaderboardEntry<'a>, UrlGenerationError> {
+    Ok(LeaderboardEntry {
+        username,
+        activity_url: UrlActivity::new(username, activity_id, req)?
+            .url
+            .to_string(),
+        effort,
+        speed: segment.distance / Time::new::<second>(effort.duration.as_secs_f64().max(1.)),
+    })
+}
+
+#[derive(Template)]
+#[template(path = "segment/segment.html")]
+struct SegmentTemplate<'a> {
+    url: UrlFor,
+    id: Identity,
+    unit: &'a Unit,
+    segment_id: u64,
+    segment: &'a Segment,
+    is_owner: bool,
+    overall: &'a [LeaderboardEntry<'a>],
+    user: Option<&'a str>,
+    personal: &'a [LeaderboardEntry<'a>],
+    title: &'a str,
+}
+
+#[derive(Deserialize)]
+struct SegmentQuery {
+    user: Option<String>,
+}
+
+async fn segment(
+    req: HttpRequest,
+    id: Identity,
+    data: web::Data<crate::Database>,
+    unit: web::Data<Unit>,
+    web::Path(segment_id): web::Path<u64>,
+    query: web::Query<SegmentQuery>,
+) -> impl Responder {
+    let segment = data.segments.get(segment_id)?;
+    let efforts = data.segments.get_efforts(segment_id)?;
+
+    let entry = |x| leaderboard_entry(x, &segment, &req);
+
+    // Efforts are sorted, so the first one of every user is their best
+    let mut seen = HashSet::new();
+    let overall = efforts
+        .iter()
+        .filter(|x| seen.insert(&x.0))
+        .map(entry)
+        .collect::<Result<Vec<LeaderboardEntry>, UrlGenerationError>>()?;
+
+    let user = query.into_inner().user.or_else(|| id.identity());
+    let personal = efforts
+        .iter()
+        .filter(|x| Some(&x.0) == user.as_ref())
+        .map(entry)
+        .collect::<Result<Vec<LeaderboardEntry>, UrlGenerationError>>()?;
+    let is_owner = id.identity().as_ref() == Some(&segment.username);
+
+    SegmentTemplate {
+        url: UrlFor::new(&id, &req)?,
+        id,
+        unit: &unit,
+        segment_id,
+        segment: &segment,
+        is_owner,
+        overall: &overall,
+        user: user.as_deref(),
+        personal: &personal,
+        title: &segment.name,
+    }
+    .into_response()
+}
+
+#[derive(Deserialize)]
+pub struct SegmentForm {
+    pub name: String,
+    pub start: f64,
+    pub end: f64,
+}
+
+/// Creates a segment from a stretch of an activity, given by the distances
+/// from the start of the activity. Every activity is matched against it in
+/// the background.
+pub async fn segment_add(
+    req: HttpRequest,
+    data: web::Data<crate::Database>,
+    unit: web::Data<Unit>,
+    web::Path((username, activity_id)): web::Path<(String, String)>,
+    form: web::Form<SegmentForm>,
+) -> actix_web::Result<HttpResponse> {
+    let activity = data.activities.get_activity(&username, &activity_id)?;
+
+    let length = |x: f64| match *unit.as_ref() {
+        Unit::Metric => Length::new::<kilometer>(x),
+        Unit::Imperial => Length::new::<mile>(x),
+    };
+
+    let name = form.name.trim();
+    if name.is_empty() {
+        return Err(Error::BadRequest(ErrorKind::BadRequest, "Segment name is missing").into());
+    }
+
+    let segment = segments::segment(
+        name,
+        &username,
+        &activity,
+        length(form.start.min(form.end)),
+        length(form.start.max(form.end)),
+    )
+    .ok_or(Error::BadRequest(
+        ErrorKind::BadRequest,
+        "Segment does not contain enough GPS data",
+    ))?;
+
+    let segment_id = data.segments.insert(&segment)?;
+    data.jobs.push(&Job::MatchSegment {
+        username: username.clone(),
+        id: segment_id,
+    })?;
+
+    let url = req.url_for("segment", &[segment_id.to_string()])?;
+
+    Ok(HttpResponse::Found()
+        .header(http::header::LOCATION, url.as_str())
+        .finish())
+}
+
+async fn segment_delete(
+    req: HttpRequest,
+    id: Identity,
+    data: web::Data<crate::Database>,
+    web::Path(segment_id): web::Path<u64>,
+) -> actix_web::Result<HttpResponse> {
+    let segment = data.segments.get(segment_id)?;
+
+    if id.identity().as_ref() != Some(&segment.username) {
+        return Err(Error::BadRequest(
+            ErrorKind::Forbidden,
+            "Only the creator of a segment can delete it",
+        )
+        .into());
+    }
+
+    data.segments.remove(segment_id)?;
+
+    let url: UrlFor = UrlFor::new(&id, &req)?;
+
+    Ok(HttpResponse::Found()
+        .header(http::header::LOCATION, url.segment_index.as_str())
+        .finish())
+}
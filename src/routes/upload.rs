@@ -1,9 +1,22 @@
-use super::UrlFor;
+use super::{UrlActivity, UrlFor};
+use crate::{
+    analysis::{fitness::training_load, track},
+    error::Error,
+    jobs::Job,
+    models::{Activity, ActivityType, Curve, Duration, Record, Session, TimeStamp, Unit},
+};
 use actix_identity::Identity;
 use actix_multipart::Multipart;
-use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use actix_web::{error::BlockingError, http, web, HttpRequest, HttpResponse, Responder};
 use askama_actix::{Template, TemplateIntoResponse};
+use chrono::{Local, NaiveDateTime, TimeZone};
 use futures::{StreamExt, TryStreamExt};
+use serde::Deserialize;
+use std::str::FromStr;
+use uom::si::{
+    f64::Length,
+    length::{kilometer, mile},
+};
 
 pub fn config(cfg: &mut web::ServiceConfig) {
     cfg.service(
@@ -11,6 +24,12 @@ pub fn config(cfg: &mut web::ServiceConfig) {
             .name("upload")
             .route(web::get().to(upload))
             .route(web::post().to(upload_post)),
+    )
+    .service(
+        web::resource("/upload/manual")
+            .name("upload_manual")
+            .route(web::get().to(upload_manual))
+            .route(web::post().to(upload_manual_post)),
     );
 }
 
@@ -49,15 +68,207 @@ async fn upload_post(
         .users
         .get_standard_gear(id.identity().unwrap().as_str())?;
 
-    let parsed = crate::parser::parse(&f, gear);
+    // Analysis is left to the background jobs, as computing the mean-maximal
+    // curves takes a while
+    let parsed = web::block(move || {
+        crate::parser::parse(&f, gear).map(|mut x| {
+            let (cleaning, raw) = track::clean(&mut x, false);
+            (x, cleaning, raw)
+        })
+    })
+    .await;
 
     match parsed {
-        Ok(x) => {
+        Ok((x, cleaning, raw)) => {
             let id = id.identity().unwrap();
-            data.activities
-                .insert(x, &id)
+            let activity_id = x.id.clone();
+            // The fitness chart includes the activity before it is analyzed
+            let load = training_load(&x.record, &data.users.get_thresholds(&id)?);
+
+            data.activities.insert(x, &id)?;
+            data.activities.set_training_load(&id, &activity_id, load)?;
+            super::heatmap::remove_cache(&id);
+            // The raw record is only kept if it differs from the cleaned one
+            if !cleaning.is_empty() {
+                data.activities
+                    .insert_raw(&id, &activity_id, &cleaning, &raw)?;
+            }
+            // The thumbnail is queued by the analysis
+            data.jobs
+                .push(&Job::Analyze {
+                    username: id,
+                    id: activity_id,
+                })
                 .map(|_| HttpResponse::Ok().finish().into_body())
         }
-        Err(x) => Ok(HttpResponse::BadRequest().body(x.to_string())),
+        Err(BlockingError::Error(x)) => Ok(HttpResponse::BadRequest().body(x.to_string())),
+        Err(BlockingError::Canceled) => Err(Error::BadServerResponse("Failed to parse file")),
+    }
+}
+
+#[derive(Template)]
+#[template(path = "upload_manual.html")]
+struct UploadManualTemplate<'a> {
+    url: UrlFor,
+    id: Identity,
+    unit: &'a Unit,
+    gears: &'a [Option<String>],
+    now: &'a str,
+    title: &'a str,
+    message: Option<&'a str>,
+}
+
+/// Gear of the user, the standard gear first.
+fn gears(data: &crate::Database, username: &str) -> Result<Vec<Option<String>>, Error> {
+    let standard_gear = data.users.get_standard_gear(username)?;
+
+    let mut gears: Vec<Option<String>> = data.gear.iter(username)?.map(|x| Some(x.name)).collect();
+    gears.push(None);
+    gears.sort_by_key(|k| k.as_ref() != standard_gear.as_ref());
+
+    Ok(gears)
+}
+
+async fn upload_manual(
+    req: HttpRequest,
+    id: Identity,
+    data: web::Data<crate::Database>,
+    unit: web::Data<Unit>,
+) -> impl Responder {
+    let gears = gears(&data, &id.identity().unwrap())?;
+
+    UploadManualTemplate {
+        url: UrlFor::new(&id, &req)?,
+        id,
+        unit: &unit,
+        gears: &gears,
+        now: &Local::now().format("%Y-%m-%dT%H:%M").to_string(),
+        title: "Add activity",
+        message: None,
+    }
+    .into_response()
+}
+
+#[derive(Deserialize)]
+struct ManualForm {
+    #[serde(default)]
+    pub title: String,
+    pub activity_type: String,
+    pub start_time: String,
+    pub duration: String,
+    #[serde(default)]
+    pub distance: String,
+    #[serde(default)]
+    pub calories: String,
+    pub gear_id: Option<String>,
+    #[serde(default)]
+    pub notes: String,
+}
+
+/// Creates an activity from the values of the form, without a record.
+fn manual_activity(
+    form: ManualForm,
+    unit: &Unit,
+    gears: &[Option<String>],
+) -> Result<Activity, &'static str> {
+    let start_time = NaiveDateTime::parse_from_str(&form.start_time, "%Y-%m-%dT%H:%M")
+        .ok()
+        .and_then(|x| Local.from_local_datetime(&x).single())
+        .ok_or("Invalid start time.")?;
+    let duration = Duration::from_str(&form.duration)
+        .ok()
+        .filter(|x| *x != Duration::default())
+        .ok_or("The duration has to be given as HH:MM:SS.")?;
+
+    let distance = match form.distance.trim() {
+        "" => None,
+        x => {
+            let x = x
+                .parse::<f64>()
+                .ok()
+                .filter(|x| *x >= 0.)
+                .ok_or("Invalid distance.")?;
+            Some(match unit {
+                Unit::Metric => Length::new::<kilometer>(x),
+                Unit::Imperial => Length::new::<mile>(x),
+            })
+        }
+    };
+    let calories = match form.calories.trim() {
+        "" => None,
+        x => Some(x.parse::<u16>().map_err(|_| "Invalid calories.")?),
+    };
+
+    let gear_id = form.gear_id.filter(|x| !x.is_empty());
+    if !gears.contains(&gear_id) {
+        return Err("The specified gear does not exist.");
+    }
+
+    let session = Session {
+        activity_type: ActivityType::from_str(&form.activity_type).unwrap_or_default(),
+        start_time: TimeStamp(start_time),
+        duration,
+        duration_active: duration,
+        distance,
+        calories,
+        ..Session::default()
+    };
+
+    Ok(Activity {
+        id: start_time.format("%Y%m%d%H%M").to_string(),
+        gear_id,
+        session,
+        record: Record::default(),
+        lap: Vec::new(),
+        curve: Curve::default(),
+        efforts: Vec::new(),
+        notes: Some(form.notes).filter(|x| !x.is_empty()),
+        title: Some(form.title.trim().to_string()).filter(|x| !x.is_empty()),
+    })
+}
+
+async fn upload_manual_post(
+    req: HttpRequest,
+    id: Identity,
+    data: web::Data<crate::Database>,
+    form: web::Form<ManualForm>,
+    unit: web::Data<Unit>,
+) -> impl Responder {
+    let username = id.identity().unwrap();
+    let gears = gears(&data, &username)?;
+
+    let result = match manual_activity(form.into_inner(), &unit, &gears) {
+        Ok(x) if data.activities.exists(&username, &x.id)? => {
+            Err("An activity starting at the same time already exists.")
+        }
+        x => x,
+    };
+
+    match result {
+        Ok(activity) => {
+            let activity_id = activity.id.clone();
+            data.activities.insert_or_overwrite(activity, &username)?;
+            // Fills in the average speed, and updates the totals and records
+            data.jobs.push(&Job::Analyze {
+                username: username.clone(),
+                id: activity_id.clone(),
+            })?;
+
+            let url = UrlActivity::new(&username, &activity_id, &req)?;
+            Ok(HttpResponse::Found()
+                .header(http::header::LOCATION, url.url.as_str())
+                .finish()
+                .into_body())
+        }
+        Err(message) => UploadManualTemplate {
+            url: UrlFor::new(&id, &req)?,
+            id,
+            unit: &unit,
+            gears: &gears,
+            now: &Local::now().format("%Y-%m-%dT%H:%M").to_string(),
+            title: "Add activity",
+            message: Some(message),
+        }
+        .into_response(),
     }
 }
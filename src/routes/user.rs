@@ -1,19 +1,47 @@
 use super::{utils, PasswordEnum, UrlFor};
-use crate::models::{DisplayUnit, Unit, UserTotals};
+use crate::{
+    analysis::{curve::BestCurve, fitness},
+    jobs::Job,
+    models::{DisplayUnit, PersonalRecord, Unit, UserTotals},
+};
 use actix_identity::Identity;
 use actix_web::{http, web, Either, HttpRequest, HttpResponse, Responder};
 use askama_actix::{Template, TemplateIntoResponse};
-use serde::Deserialize;
+use chrono::{Datelike, Local};
+use serde::{de, Deserialize, Deserializer};
+use std::{fmt::Display, str::FromStr};
+use uom::si::{f64::Mass, mass::kilogram};
 
 pub fn config(cfg: &mut web::ServiceConfig) {
     cfg.service(web::resource("/").name("user_index").to(user_index))
         .service(web::resource("/{username}").name("user").to(user))
+        .service(
+            web::resource("/{username}/records")
+                .name("user_records")
+                .to(user_records),
+        )
+        .service(
+            web::resource("/{username}/fitness")
+                .name("user_fitness")
+                .to(user_fitness),
+        )
+        .service(
+            web::resource("/{username}/curve")
+                .name("user_curve")
+                .to(user_curve),
+        )
         .service(
             web::resource("/{username}/settings")
                 .name("user_settings")
                 .wrap(crate::middleware::Restricted)
                 .route(web::get().to(user_settings))
                 .route(web::post().to(user_settings_post)),
+        )
+        .service(
+            web::resource("/{username}/reanalyze")
+                .name("user_reanalyze")
+                .wrap(crate::middleware::Restricted)
+                .route(web::post().to(user_reanalyze)),
         );
 }
 
@@ -50,6 +78,149 @@ async fn user(
     .into_response()
 }
 
+#[derive(Template)]
+#[template(path = "user/records.html")]
+struct UserRecordsTemplate<'a> {
+    url: UrlFor,
+    id: Identity,
+    records: &'a [PersonalRecord],
+    username: &'a str,
+    title: &'a str,
+}
+
+async fn user_records(
+    req: HttpRequest,
+    id: Identity,
+    data: web::Data<crate::Database>,
+    username: web::Path<String>,
+) -> impl Responder {
+    data.users.exists(&username)?;
+
+    let mut records = data.activities.get_personal_records(&username)?;
+    records.reverse();
+
+    UserRecordsTemplate {
+        url: UrlFor::new(&id, &req)?,
+        id,
+        records: &records,
+        username: &username,
+        title: "Personal records",
+    }
+    .into_response()
+}
+
+#[derive(Template)]
+#[template(path = "user/fitness.html")]
+struct UserFitnessTemplate<'a> {
+    url: UrlFor,
+    id: Identity,
+    today: Option<&'a fitness::FitnessDay>,
+    plot: &'a str,
+    title: &'a str,
+}
+
+async fn user_fitness(
+    req: HttpRequest,
+    id: Identity,
+    data: web::Data<crate::Database>,
+    username: web::Path<String>,
+) -> impl Responder {
+    data.users.exists(&username)?;
+
+    let mut loads = Vec::new();
+    for (activity_id, session) in data
+        .activities
+        .username_iter_id(&username)?
+        .zip(data.activities.username_iter_session(&username)?)
+    {
+        if let Some(load) = data.activities.get_training_load(&username, &activity_id)? {
+            loads.push((session.start_time.0.naive_local().date(), load));
+        }
+    }
+
+    let days = fitness::fitness(loads, Local::now().naive_local().date());
+    let plot = utils::fitness_plot(&days);
+
+    UserFitnessTemplate {
+        url: UrlFor::new(&id, &req)?,
+        id,
+        today: days.last(),
+        plot: &plot,
+        title: "Fitness",
+    }
+    .into_response()
+}
+
+#[derive(Template)]
+#[template(path = "user/curve.html")]
+struct UserCurveTemplate<'a> {
+    url: UrlFor,
+    id: Identity,
+    power: &'a str,
+    heartrate: &'a str,
+    pace: &'a str,
+    username: &'a str,
+    title: &'a str,
+}
+
+async fn user_curve(
+    req: HttpRequest,
+    id: Identity,
+    data: web::Data<crate::Database>,
+    username: web::Path<String>,
+    unit: web::Data<Unit>,
+) -> impl Responder {
+    data.users.exists(&username)?;
+
+    let now = Local::now();
+    let (mut all, mut year, mut recent) = (
+        BestCurve::default(),
+        BestCurve::default(),
+        BestCurve::default(),
+    );
+
+    for (activity_id, session) in data
+        .activities
+        .username_iter_id(&username)?
+        .zip(data.activities.username_iter_session(&username)?)
+    {
+        let curve = data
+            .activities
+            .get_curve(&username, &activity_id)
+            .unwrap_or_default();
+        // Pace is only comparable between runs
+        let running = session.activity_type.is_running();
+
+        all.update(&activity_id, &curve, running);
+        if session.start_time.0.year() == now.year() {
+            year.update(&activity_id, &curve, running);
+        }
+        if session.start_time.0 > now - chrono::Duration::days(90) {
+            recent.update(&activity_id, &curve, running);
+        }
+    }
+
+    let (power, heartrate, pace) = utils::curve_plot(
+        &[
+            ("All time", &all),
+            ("This year", &year),
+            ("Last 90 days", &recent),
+        ],
+        &unit,
+    );
+
+    UserCurveTemplate {
+        url: UrlFor::new(&id, &req)?,
+        id,
+        power: &power,
+        heartrate: &heartrate,
+        pace: &pace,
+        username: &username,
+        title: "Curves",
+    }
+    .into_response()
+}
+
 #[derive(Template)]
 #[template(path = "user/index.html")]
 struct UserIndexTemplate<'a> {
@@ -76,9 +247,26 @@ async fn user_index(
 }
 
 #[derive(Deserialize, Debug)]
-struct HeartrateForm {
+struct ThresholdForm {
     heartrate_rest: u8,
     heartrate_max: u8,
+    #[serde(default, deserialize_with = "empty_as_none")]
+    ftp: Option<u16>,
+    #[serde(default, deserialize_with = "empty_as_none")]
+    weight: Option<f64>,
+}
+
+/// Parses a form field which may be left empty.
+fn empty_as_none<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    match String::deserialize(deserializer)?.trim() {
+        "" => Ok(None),
+        x => x.parse().map(Some).map_err(de::Error::custom),
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -94,6 +282,8 @@ struct UserSettingsTemplate<'a> {
     url: UrlFor,
     id: Identity,
     heartrate: &'a Option<(u8, u8)>,
+    ftp: Option<u16>,
+    weight: Option<f64>,
     message: &'a Option<crate::error::Error>,
     title: &'a str,
 }
@@ -105,11 +295,18 @@ async fn user_settings(
     username: web::Path<String>,
 ) -> impl Responder {
     let heartrate = data.users.get_heartrate(&username)?;
+    let ftp = data.users.get_ftp(&username)?;
+    let weight = data
+        .users
+        .get_weight(&username)?
+        .map(|x| x.get::<kilogram>());
 
     UserSettingsTemplate {
         url: UrlFor::new(&id, &req)?,
         id,
         heartrate: &heartrate,
+        ftp,
+        weight,
         message: &None,
         title: "Settings",
     }
@@ -121,12 +318,24 @@ async fn user_settings_post(
     id: Identity,
     username: web::Path<String>,
     data: web::Data<crate::Database>,
-    form: Either<web::Form<HeartrateForm>, web::Form<PasswordForm>>,
+    form: Either<web::Form<ThresholdForm>, web::Form<PasswordForm>>,
 ) -> impl Responder {
     let form_result = match form {
         Either::A(x) => {
             data.users
                 .set_heartrate(&username, (x.heartrate_rest, x.heartrate_max))?;
+            data.users.set_ftp(&username, x.ftp.filter(|x| *x > 0))?;
+            data.users.set_weight(
+                &username,
+                x.weight.filter(|x| *x > 0.).map(Mass::new::<kilogram>),
+            )?;
+
+            // Training load of every activity depends on the thresholds
+            let thresholds = data.users.get_thresholds(&username)?;
+            let (data, username) = (data.clone(), username.clone());
+            web::block(move || data.activities.update_training_load(&username, &thresholds))
+                .await?;
+
             Ok(())
         }
         Either::B(x) => {
@@ -149,13 +358,37 @@ async fn user_settings_post(
             .into_body())
     } else {
         let heartrate = data.users.get_heartrate(&username)?;
+        let ftp = data.users.get_ftp(&username)?;
+        let weight = data
+            .users
+            .get_weight(&username)?
+            .map(|x| x.get::<kilogram>());
         UserSettingsTemplate {
             url,
             id,
             heartrate: &heartrate,
+            ftp,
+            weight,
             message: &form_result.err(),
             title: "Settings",
         }
         .into_response()
     }
 }
+
+async fn user_reanalyze(
+    req: HttpRequest,
+    id: Identity,
+    data: web::Data<crate::Database>,
+    username: web::Path<String>,
+) -> actix_web::Result<HttpResponse> {
+    data.jobs.push(&Job::Reanalyze {
+        username: username.into_inner(),
+    })?;
+
+    let url: UrlFor = UrlFor::new(&id, &req)?;
+
+    Ok(HttpResponse::Found()
+        .header(http::header::LOCATION, url.user.as_str())
+        .finish())
+}
@@ -1,20 +1,31 @@
-use super::PasswordEnum;
+use super::{
+    api::{ClimbSpan, Series, Trace},
+    PasswordEnum,
+};
 use crate::{
+    analysis::{
+        climbs::Climb,
+        curve::{self, BestCurve},
+        downsample,
+        fitness::{training_load, FitnessDay},
+        grade::{self, GradeAdjusted},
+    },
     error::{Error, ErrorKind, Result},
-    models::{Duration, Record, Unit},
+    jobs::Job,
+    models::{Activity, Duration, GetWithUnit, Record, Session, Unit},
 };
 use actix_web::web;
 use plotly::{
-    common::Mode,
-    layout::{Axis, Layout},
-    Plot, Scatter,
+    common::{Mode, Orientation, Side},
+    layout::{Axis, AxisType, HoverMode, Layout, Legend},
+    Bar, Plot, Scatter,
 };
-use staticmap::{
-    tools::{Color, LineBuilder},
-    StaticMapBuilder,
+use pulldown_cmark::{html, CowStr, Event, Options, Parser, Tag};
+use uom::si::velocity::{kilometer_per_hour, meter_per_second, mile_per_hour};
+use uom::si::{
+    f64::{Length, Velocity},
+    length::{foot, kilometer, meter, mile},
 };
-use uom::si::length::{foot, kilometer, meter, mile};
-use uom::si::velocity::{kilometer_per_hour, mile_per_hour};
 
 pub fn validate_form(form: &super::PasswordEnum, data: &web::Data<crate::Database>) -> Result<()> {
     let verify_hash = |username, password| data.users.verify_hash(username, password);
@@ -80,89 +91,628 @@ pub fn validate_form(form: &super::PasswordEnum, data: &web::Data<crate::Databas
     Ok(())
 }
 
-pub fn plot(record: &Record, unit: &Unit) -> Result<String> {
-    // x-axis
-    let distance = record.distance.iter().map(|x| match unit {
-        Unit::Metric => x.map(|y| format!("{:.2}", y.get::<kilometer>())),
-        Unit::Imperial => x.map(|y| format!("{:.2}", y.get::<mile>())),
-    });
+/// Renders Markdown as HTML which is safe to show unescaped. Raw HTML is
+/// shown as text, and links and images only keep addresses of web pages and
+/// mail addresses.
+pub fn markdown(text: &str) -> String {
+    fn safe(url: CowStr<'_>) -> CowStr<'_> {
+        let scheme = url
+            .split([':', '/', '?', '#'])
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
 
-    // Heart rate
-    let heartrate = Scatter::new(distance.clone(), record.heartrate.clone())
-        .mode(Mode::Lines)
-        .name("Heart rate");
+        match url.contains(':') && !matches!(scheme.as_str(), "http" | "https" | "mailto") {
+            true => CowStr::Borrowed(""),
+            false => url,
+        }
+    }
+
+    let parser =
+        Parser::new_ext(text, Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH).map(|x| {
+            match x {
+                Event::Html(x) => Event::Text(x),
+                Event::Start(Tag::Link(kind, url, title)) => {
+                    Event::Start(Tag::Link(kind, safe(url), title))
+                }
+                Event::Start(Tag::Image(kind, url, title)) => {
+                    Event::Start(Tag::Image(kind, safe(url), title))
+                }
+                x => x,
+            }
+        });
 
-    // Speed
-    let speed_map = record.speed.iter().map(|x| match unit {
-        Unit::Metric => x.map(|y| format!("{:.2}", y.get::<kilometer_per_hour>())),
-        Unit::Imperial => x.map(|y| format!("{:.2}", y.get::<mile_per_hour>())),
-    });
+    let mut output = String::new();
+    html::push_html(&mut output, parser);
+    output
+}
 
-    let speed = Scatter::new(distance.clone(), speed_map)
-        .mode(Mode::Lines)
-        .name("Speed");
+/// Overwrites an activity after its record has been edited, and updates
+/// the values derived from it which depend on other activities or user settings.
+pub fn update_activity(data: &crate::Database, username: &str, activity: Activity) -> Result<()> {
+    update_dependents(data, username, &activity)?;
+    let id = activity.id.clone();
+    data.activities.insert_or_overwrite(activity, username)?;
+    super::heatmap::remove_cache(username);
+
+    data.activities.update_personal_records(username, &id)
+}
+
+/// Stores what the analysis derived from the record of an activity, which
+/// is otherwise left as it is, and updates what depends on it like
+/// `update_activity`.
+pub fn update_analysis(data: &crate::Database, username: &str, activity: &Activity) -> Result<()> {
+    update_dependents(data, username, activity)?;
+    data.activities.set_analysis(username, activity)?;
 
-    // Altitude
-    let altitude_map = record.altitude.iter().map(|x| match unit {
-        Unit::Metric => x.map(|y| format!("{:.2}", y.get::<meter>())),
-        Unit::Imperial => x.map(|y| format!("{:.2}", y.get::<foot>())),
-    });
+    data.activities
+        .update_personal_records(username, &activity.id)
+}
+
+/// Matches an activity against segments and routes, and updates its
+/// thumbnail and training load.
+fn update_dependents(data: &crate::Database, username: &str, activity: &Activity) -> Result<()> {
+    let load = training_load(&activity.record, &data.users.get_thresholds(username)?);
+    let id = activity.id.clone();
+
+    data.segments.update_efforts(username, activity)?;
+    data.routes.assign(username, activity)?;
+
+    // The thumbnail is only rendered again if the track changed
+    let thumbnail = Job::Thumbnail {
+        username: username.to_string(),
+        id: id.clone(),
+    };
+    if !data.jobs.is_pending(&thumbnail)? {
+        data.jobs.push(&thumbnail)?;
+    }
 
-    let altitude = Scatter::new(distance, altitude_map)
+    data.activities.set_training_load(username, &id, load)
+}
+
+// Colors of the traces and their y-axes, in order
+pub const PLOT_COLORS: [&str; 6] = [
+    "#d62728", "#1f77b4", "#2ca02c", "#9467bd", "#ff7f0e", "#8c564b",
+];
+// Horizontal space between the y-axes on the same side, as a fraction of the plot
+const AXIS_SPACING: f64 = 0.05;
+
+/// Series of every recorded channel for the plot of an activity, each on its
+/// own y-axis, downsampled to at most `resolution` points. The layout shows
+/// distance on the x-axis, or elapsed time if the activity has no distance.
+pub fn series(record: &Record, climbs: &[Climb], unit: &Unit, resolution: usize) -> Series {
+    let by_distance = record.distance.iter().any(Option::is_some);
+    let distance: Vec<Option<f64>> = record
+        .distance
+        .iter()
+        .map(|x| x.map(|y| (y.get_with_unit(unit) * 1000.).round() / 1000.))
+        .collect();
+    // Milliseconds, which plotly shows as a time of day on a date axis
+    let time: Vec<u64> = record
+        .duration
+        .iter()
+        .map(|x| (x.as_secs_f64() * 1000.) as u64)
+        .collect();
+
+    let channels: Vec<(&str, &str, Vec<Option<f64>>)> = vec![
+        (
+            "Heart rate",
+            " bpm",
+            record
+                .heartrate
+                .iter()
+                .map(|x| x.map(f64::from))
+                .collect::<Vec<Option<f64>>>(),
+        ),
+        (
+            "Speed",
+            match unit {
+                Unit::Metric => " km/h",
+                Unit::Imperial => " mph",
+            },
+            record
+                .speed
+                .iter()
+                .map(|x| {
+                    x.map(|y| match unit {
+                        Unit::Metric => y.get::<kilometer_per_hour>(),
+                        Unit::Imperial => y.get::<mile_per_hour>(),
+                    })
+                })
+                .collect::<Vec<Option<f64>>>(),
+        ),
+        (
+            "Altitude",
+            match unit {
+                Unit::Metric => " m",
+                Unit::Imperial => " ft",
+            },
+            record
+                .altitude
+                .iter()
+                .map(|x| {
+                    x.map(|y| match unit {
+                        Unit::Metric => y.get::<meter>(),
+                        Unit::Imperial => y.get::<foot>(),
+                    })
+                })
+                .collect::<Vec<Option<f64>>>(),
+        ),
+        (
+            "Power",
+            " W",
+            record
+                .power
+                .iter()
+                .map(|x| x.map(f64::from))
+                .collect::<Vec<Option<f64>>>(),
+        ),
+        (
+            "Cadence",
+            " rpm",
+            record
+                .cadence
+                .iter()
+                .map(|x| x.map(f64::from))
+                .collect::<Vec<Option<f64>>>(),
+        ),
+        (
+            "Temperature",
+            match unit {
+                Unit::Metric => " °C",
+                Unit::Imperial => " °F",
+            },
+            record
+                .temperature
+                .iter()
+                .map(|x| {
+                    x.map(|y| match unit {
+                        Unit::Metric => f64::from(y),
+                        Unit::Imperial => f64::from(y) * 1.8 + 32.,
+                    })
+                })
+                .collect::<Vec<Option<f64>>>(),
+        ),
+    ]
+    .into_iter()
+    .filter(|x| x.2.iter().any(Option::is_some))
+    .collect();
+
+    // The y-axes alternate between the left and the right of the plot,
+    // moving outwards, and the plot narrows to make room for them
+    let left = channels.len().div_ceil(2);
+    let right = channels.len() / 2;
+    let domain = [
+        left.saturating_sub(1) as f64 * AXIS_SPACING,
+        1. - right.saturating_sub(1) as f64 * AXIS_SPACING,
+    ];
+
+    let mut x_axis = Axis::new().domain(&domain);
+    x_axis = match (by_distance, unit) {
+        (true, Unit::Metric) => x_axis.tick_suffix(" km"),
+        (true, Unit::Imperial) => x_axis.tick_suffix(" mi"),
+        (false, _) => x_axis.type_(AxisType::Date).tick_format("%H:%M:%S"),
+    };
+
+    let mut layout = Layout::new()
+        .x_axis(x_axis)
+        .hover_mode(HoverMode::X)
+        .legend(Legend::new().orientation(Orientation::Horizontal));
+    let mut traces = Vec::new();
+
+    for (i, (name, suffix, values)) in channels.into_iter().enumerate() {
+        let color = PLOT_COLORS[i % PLOT_COLORS.len()];
+
+        // Distance from the plot, in axes
+        let offset = (i / 2) as f64;
+        let mut axis = Axis::new()
+            .tick_suffix(suffix)
+            .color(color)
+            .show_grid(i == 0)
+            .zero_line(false);
+        axis = match (i % 2, offset == 0.) {
+            (0, true) => axis.anchor("x").side(Side::Left),
+            (_, true) => axis.anchor("x").side(Side::Right),
+            (0, false) => axis
+                .anchor("free")
+                .side(Side::Left)
+                .position(domain[0] - offset * AXIS_SPACING),
+            (_, false) => axis
+                .anchor("free")
+                .side(Side::Right)
+                .position(domain[1] + offset * AXIS_SPACING),
+        };
+        if i > 0 {
+            axis = axis.overlaying("y");
+        }
+
+        layout = match i {
+            0 => layout.y_axis(axis),
+            1 => layout.y_axis2(axis),
+            2 => layout.y_axis3(axis),
+            3 => layout.y_axis4(axis),
+            4 => layout.y_axis5(axis),
+            _ => layout.y_axis6(axis),
+        };
+
+        // Downsampled over time, which every sample has
+        let points: Vec<(usize, f64)> = values
+            .iter()
+            .enumerate()
+            .filter_map(|(i, x)| Some((i, (*x)?)))
+            .collect();
+        let samples: Vec<usize> = downsample::lttb(
+            &points
+                .iter()
+                .map(|(i, x)| (time[*i] as f64, *x))
+                .collect::<Vec<(f64, f64)>>(),
+            resolution,
+        )
+        .into_iter()
+        .map(|x| points[x].0)
+        .collect();
+
+        traces.push(Trace {
+            name,
+            y_axis: match i {
+                0 => "y".to_string(),
+                _ => format!("y{}", i + 1),
+            },
+            color,
+            distance: samples.iter().map(|x| distance[*x]).collect(),
+            time: samples.iter().map(|x| time[*x]).collect(),
+            values: samples
+                .iter()
+                .filter_map(|x| values[*x])
+                .map(|x| (x * 100.).round() / 100.)
+                .collect(),
+            samples,
+        });
+    }
+
+    Series {
+        by_distance,
+        layout,
+        traces,
+        climbs: climbs
+            .iter()
+            .map(|x| ClimbSpan {
+                distance: [distance[x.start], distance[x.end]],
+                time: [time[x.start], time[x.end]],
+            })
+            .collect(),
+    }
+}
+
+/// Renders the power, heart rate and pace curves, with one trace for each
+/// of the supplied time periods. Every point carries the id of the activity
+/// that set it, for linking from the chart.
+pub fn curve_plot(curves: &[(&str, &BestCurve)], unit: &Unit) -> (String, String, String) {
+    let layout = |y_suffix: &str| {
+        let (tick_values, tick_text) = [
+            (1., "1s"),
+            (5., "5s"),
+            (15., "15s"),
+            (60., "1m"),
+            (300., "5m"),
+            (1200., "20m"),
+            (3600., "1h"),
+            (3. * 3600., "3h"),
+            (6. * 3600., "6h"),
+        ]
+        .iter()
+        .map(|(x, y)| (*x, y.to_string()))
+        .unzip();
+
+        Layout::new()
+            .hover_mode(HoverMode::X)
+            .x_axis(
+                Axis::new()
+                    .type_(AxisType::Log)
+                    .tick_values(tick_values)
+                    .tick_text(tick_text),
+            )
+            .y_axis(Axis::new().tick_suffix(y_suffix))
+    };
+
+    let render = |traces: Vec<Box<Scatter<usize, f64>>>, y_suffix: &str, div_id: &'static str| {
+        let mut plot = Plot::new();
+        plot.set_layout(layout(y_suffix));
+        for trace in traces {
+            plot.add_trace(trace);
+        }
+        plot.to_inline_html(div_id)
+    };
+
+    let trace = |name: &str, points: Vec<(usize, f64, &String)>| {
+        let (x, (y, ids)): (Vec<usize>, (Vec<f64>, Vec<&String>)) =
+            points.into_iter().map(|(x, y, z)| (x, (y, z))).unzip();
+
+        Scatter::new(x, y)
+            .mode(Mode::Lines)
+            .name(name)
+            .custom_data(ids)
+    };
+
+    let power = curves
+        .iter()
+        .map(|(name, curve)| {
+            trace(
+                name,
+                curve::durations()
+                    .zip(&curve.power)
+                    .map(|(x, y)| (x, f64::from(y.0), &y.1))
+                    .collect(),
+            )
+        })
+        .collect();
+
+    let heartrate = curves
+        .iter()
+        .map(|(name, curve)| {
+            trace(
+                name,
+                curve::durations()
+                    .zip(&curve.heartrate)
+                    .map(|(x, y)| (x, f64::from(y.0), &y.1))
+                    .collect(),
+            )
+        })
+        .collect();
+
+    let unit_length = match unit {
+        Unit::Metric => Length::new::<kilometer>(1.),
+        Unit::Imperial => Length::new::<mile>(1.),
+    };
+
+    // Pace in minutes per kilometer or mile
+    let pace = curves
+        .iter()
+        .map(|(name, curve)| {
+            trace(
+                name,
+                curve::durations()
+                    .zip(&curve.speed)
+                    .filter(|(_, y)| y.0.get::<meter_per_second>() > 0.)
+                    .map(|(x, y)| {
+                        (
+                            x,
+                            unit_length.get::<meter>() / y.0.get::<meter_per_second>() / 60.,
+                            &y.1,
+                        )
+                    })
+                    .collect(),
+            )
+        })
+        .collect();
+
+    let pace_suffix = match unit {
+        Unit::Metric => " min/km",
+        Unit::Imperial => " min/mi",
+    };
+
+    (
+        render(power, " W", "power_curve"),
+        render(heartrate, " bpm", "heartrate_curve"),
+        render(pace, pace_suffix, "pace_curve"),
+    )
+}
+
+/// Renders the performance management chart, with daily training load as bars
+/// on a secondary axis.
+pub fn fitness_plot(days: &[FitnessDay]) -> String {
+    let dates = days
+        .iter()
+        .map(|x| x.date.format("%Y-%m-%d").to_string())
+        .collect::<Vec<String>>();
+
+    let line = |name: &str, value: fn(&FitnessDay) -> f64| {
+        Scatter::new(
+            dates.clone(),
+            days.iter()
+                .map(|x| format!("{:.1}", value(x)))
+                .collect::<Vec<String>>(),
+        )
         .mode(Mode::Lines)
-        .name("Altitude");
+        .name(name)
+    };
+
+    let load = Bar::new(
+        dates.clone(),
+        days.iter()
+            .map(|x| format!("{:.0}", x.load))
+            .collect::<Vec<String>>(),
+    )
+    .name("Training load")
+    .opacity(0.4)
+    .y_axis("y2");
+
+    let layout = Layout::new()
+        .hover_mode(HoverMode::X)
+        .x_axis(Axis::new().type_(AxisType::Date))
+        .y_axis2(
+            Axis::new()
+                .overlaying("y")
+                .side(plotly::common::Side::Right)
+                .show_grid(false),
+        );
 
     let mut plot = Plot::new();
+    plot.set_layout(layout);
+    plot.add_trace(load);
+    plot.add_trace(line("Fitness (CTL)", |x| x.fitness));
+    plot.add_trace(line("Fatigue (ATL)", |x| x.fatigue));
+    plot.add_trace(line("Form (TSB)", |x| x.form));
+
+    plot.to_inline_html(None)
+}
 
-    let tick_suffix = match unit {
-        Unit::Metric => " km",
-        Unit::Imperial => " mi",
+/// Renders the duration of every attempt at a route over time, with average
+/// heart rate and power on a secondary axis.
+pub fn route_plot(sessions: &[Session]) -> String {
+    let dates = sessions
+        .iter()
+        .map(|x| x.start_time.0.format("%Y-%m-%d %H:%M").to_string())
+        .collect::<Vec<String>>();
+
+    let line = |name: &str, values: Vec<Option<String>>| {
+        let (x, y): (Vec<String>, Vec<String>) = dates
+            .iter()
+            .zip(values)
+            .filter_map(|(x, y)| Some((x.clone(), y?)))
+            .unzip();
+        Scatter::new(x, y).mode(Mode::LinesMarkers).name(name)
     };
 
-    let axis = Axis::new().tick_suffix(tick_suffix);
-    let layout = Layout::new().x_axis(axis);
+    let duration = line(
+        "Duration (min)",
+        sessions
+            .iter()
+            .map(|x| Some(format!("{:.1}", x.duration_active.as_secs_f64() / 60.)))
+            .collect(),
+    );
+    let heartrate = line(
+        "Avg. heart rate (bpm)",
+        sessions
+            .iter()
+            .map(|x| x.heartrate_avg.map(|y| y.to_string()))
+            .collect(),
+    )
+    .y_axis("y2");
+    let power = line(
+        "Avg. power (W)",
+        sessions
+            .iter()
+            .map(|x| x.power_avg.map(|y| y.to_string()))
+            .collect(),
+    )
+    .y_axis("y2");
+
+    let layout = Layout::new()
+        .hover_mode(HoverMode::X)
+        .x_axis(Axis::new().type_(AxisType::Date))
+        .y_axis2(
+            Axis::new()
+                .overlaying("y")
+                .side(plotly::common::Side::Right)
+                .show_grid(false),
+        );
 
+    let mut plot = Plot::new();
     plot.set_layout(layout);
+    plot.add_trace(duration);
     plot.add_trace(heartrate);
-    plot.add_trace(speed);
-    plot.add_trace(altitude);
+    plot.add_trace(power);
 
-    Ok(plot.to_inline_html(None))
+    plot.to_inline_html(Some("route_plot"))
 }
 
-pub fn generate_thumb(record: Record, path: &std::path::PathBuf) -> Result<()> {
-    if record.lon.is_empty() {
-        return Ok(());
+/// Renders the pace against the gradient of every sample of a run, both
+/// as recorded and grade-adjusted.
+pub fn gradient_plot(record: &Record, grade: &GradeAdjusted, unit: &Unit) -> String {
+    let unit_length = match unit {
+        Unit::Metric => Length::new::<kilometer>(1.),
+        Unit::Imperial => Length::new::<mile>(1.),
     }
+    .get::<meter>();
 
-    let mut map = StaticMapBuilder::default()
-        .width(200)
-        .height(200)
-        .url_template("https://a.tile.openstreetmap.org/{z}/{x}/{y}.png")
-        .build()
-        .unwrap();
-
-    let line = LineBuilder::default()
-        .width(3.)
-        .simplify(true)
-        .lon_coordinates(record.lon.into_iter().flatten().collect::<Vec<f64>>())
-        .lat_coordinates(record.lat.into_iter().flatten().collect::<Vec<f64>>())
-        .color(Color::new(true, 255, 0, 0, 255))
-        .tolerance(2)
-        .build()
-        .unwrap();
-
-    map.add_line(line);
-
-    if !path.exists() {
-        std::fs::create_dir_all(path.parent().unwrap())
-            .map_err(|_| Error::BadServerResponse("Failed to create thumbnail directory"))?;
-    }
+    // Standing still has no meaningful pace
+    let pace = |speed: Option<Velocity>| {
+        speed
+            .map(|x| x.get::<meter_per_second>())
+            .filter(|x| *x > 1.)
+            .map(|x| unit_length / x / 60.)
+    };
 
-    map.save_png(path)
-        .map_err(|_| Error::BadServerResponse("Failed to save rendered activity thumbnail"))?;
+    let trace = |name: &str, speed: &mut dyn Iterator<Item = Option<Velocity>>| {
+        let (x, y): (Vec<String>, Vec<String>) = grade
+            .gradient
+            .iter()
+            .zip(speed)
+            .filter_map(|(gradient, speed)| Some((gradient.as_ref()?, pace(speed)?)))
+            .map(|(x, y)| (format!("{:.1}", x * 100.), format!("{:.2}", y)))
+            .unzip();
 
-    Ok(())
+        Scatter::new(x, y)
+            .mode(Mode::Markers)
+            .name(name)
+            .opacity(0.5)
+    };
+
+    let pace_suffix = match unit {
+        Unit::Metric => " min/km",
+        Unit::Imperial => " min/mi",
+    };
+
+    let layout = Layout::new()
+        .x_axis(Axis::new().tick_suffix(" %"))
+        .y_axis(Axis::new().tick_suffix(pace_suffix));
+
+    let mut plot = Plot::new();
+    plot.set_layout(layout);
+    plot.add_trace(trace("Pace", &mut record.speed.iter().copied()));
+    plot.add_trace(trace(
+        "Grade-adjusted pace",
+        &mut grade.speed.iter().copied(),
+    ));
+
+    plot.to_inline_html("gradient_plot")
+}
+
+/// A value along the track which the map can color it by, as
+/// (name, unit, values at every coordinate as a JavaScript array body).
+pub type TrackMetric = (&'static str, &'static str, String);
+
+/// Speed, heart rate, power and gradient at every sample with coordinates,
+/// leaving out the ones which were not recorded.
+pub fn track_metrics(record: &Record, unit: &Unit) -> Vec<TrackMetric> {
+    let track: Vec<usize> = (0..record.lat.len())
+        .filter(|&i| record.lat[i].is_some() && record.lon[i].is_some())
+        .collect();
+
+    let metric = |name, suffix, value: &dyn Fn(usize) -> Option<f64>| {
+        let values: Vec<Option<f64>> = track.iter().map(|&i| value(i)).collect();
+        if values.iter().all(Option::is_none) {
+            return None;
+        }
+
+        let values = values
+            .iter()
+            .map(|x| match x {
+                Some(x) => format!("{:.1}", x),
+                None => "null".to_string(),
+            })
+            .collect::<Vec<String>>()
+            .join(",");
+        Some((name, suffix, values))
+    };
+
+    let gradient = grade::gradient(record);
+    let speed_unit = match unit {
+        Unit::Metric => "km/h",
+        Unit::Imperial => "mph",
+    };
+
+    vec![
+        metric("Speed", speed_unit, &|i| {
+            record.speed.get(i).copied().flatten().map(|x| match unit {
+                Unit::Metric => x.get::<kilometer_per_hour>(),
+                Unit::Imperial => x.get::<mile_per_hour>(),
+            })
+        }),
+        metric("Heart rate", "bpm", &|i| {
+            record.heartrate.get(i).copied().flatten().map(f64::from)
+        }),
+        metric("Power", "W", &|i| {
+            record.power.get(i).copied().flatten().map(f64::from)
+        }),
+        metric("Gradient", "%", &|i| {
+            gradient.get(i).copied().flatten().map(|x| x * 100.)
+        }),
+    ]
+    .into_iter()
+    .flatten()
+    .collect()
 }
 
 pub fn zone_duration(record: &Record, heartrate: &Option<(u8, u8)>) -> Option<[Duration; 6]> {
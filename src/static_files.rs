@@ -11,10 +11,6 @@ pub fn config(cfg: &mut web::ServiceConfig) {
         .service(
             web::resource("/static/img/favicon.png").route(web::get().to(embedded::serve_favicon)),
         )
-        .service(actix_files::Files::new(
-            "/static/img/activity",
-            "static/img/activity",
-        ))
         .service(web::resource("/static").name("static"));
 }
 
@@ -0,0 +1,145 @@
+use crate::error::{Error, ErrorKind, Result};
+use actix_web::{error::BlockingError, http, web, HttpResponse};
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+const CACHE_DIRECTORY: &str = "cache/tiles";
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::resource("/tiles/{z}/{x}/{y}.png").route(web::get().to(tile)));
+}
+
+async fn tile(
+    tiles: web::Data<TileSource>,
+    web::Path((z, x, y)): web::Path<(u8, u32, u32)>,
+) -> actix_web::Result<HttpResponse> {
+    let image = match web::block(move || tiles.get(z, x, y)).await {
+        Ok(x) => x,
+        Err(BlockingError::Error(x)) => return Err(x.into()),
+        Err(BlockingError::Canceled) => {
+            return Err(Error::BadServerResponse("Failed to get map tile").into())
+        }
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(content_type(&image))
+        .header(http::header::CACHE_CONTROL, "public, max-age=604800")
+        .body(image))
+}
+
+/// Source of the raster map tiles, which are either read from a local
+/// MBTiles file, or fetched from a tile server and cached on disk.
+#[derive(Clone)]
+pub struct TileSource {
+    url: String,
+    /// Connection to the MBTiles file, shared by all requests
+    mbtiles: Option<Arc<Mutex<Connection>>>,
+    max_age: Duration,
+    /// Whether thumbnails are drawn on top of tiles
+    pub thumbnails: bool,
+}
+
+impl TileSource {
+    pub fn new(config: &crate::config::Config) -> Self {
+        let mbtiles = config.mbtiles.as_ref().map(|x| {
+            let connection = Connection::open_with_flags(x, OpenFlags::SQLITE_OPEN_READ_ONLY)
+                .expect("Failed to open MBTiles file");
+            Arc::new(Mutex::new(connection))
+        });
+
+        Self {
+            url: config.tile_url.clone(),
+            mbtiles,
+            max_age: Duration::from_secs(config.tile_cache_days * 24 * 60 * 60),
+            thumbnails: config.thumbnail_tiles,
+        }
+    }
+
+    /// Gets the image of a tile. This blocks on file and network IO.
+    pub fn get(&self, z: u8, x: u32, y: u32) -> Result<Vec<u8>> {
+        if z > 22 || x >= 1 << z || y >= 1 << z {
+            return Err(Error::BadRequest(ErrorKind::NotFound, "Tile not found"));
+        }
+
+        match &self.mbtiles {
+            Some(connection) => Self::get_mbtiles(connection, z, x, y),
+            None => self.get_cached(z, x, y),
+        }
+    }
+
+    fn get_mbtiles(connection: &Mutex<Connection>, z: u8, x: u32, y: u32) -> Result<Vec<u8>> {
+        // MBTiles numbers rows from the bottom
+        connection
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT tile_data FROM tiles WHERE zoom_level = ?1 AND tile_column = ?2 AND tile_row = ?3",
+                params![z, x, (1u32 << z) - 1 - y],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(|_| Error::BadServerResponse("Failed to read MBTiles file"))?
+            .ok_or(Error::BadRequest(ErrorKind::NotFound, "Tile not found"))
+    }
+
+    fn get_cached(&self, z: u8, x: u32, y: u32) -> Result<Vec<u8>> {
+        let path = PathBuf::from(CACHE_DIRECTORY)
+            .join(z.to_string())
+            .join(x.to_string())
+            .join(format!("{}.png", y));
+
+        let fresh = std::fs::metadata(&path)
+            .and_then(|x| x.modified())
+            .ok()
+            .and_then(|x| SystemTime::now().duration_since(x).ok())
+            .is_some_and(|x| x < self.max_age);
+
+        if fresh {
+            if let Ok(x) = std::fs::read(&path) {
+                return Ok(x);
+            }
+        }
+
+        let url = self
+            .url
+            .replace("{z}", &z.to_string())
+            .replace("{x}", &x.to_string())
+            .replace("{y}", &y.to_string());
+
+        let fetched = attohttpc::get(url)
+            .header(
+                "User-Agent",
+                concat!("tf-viewer/", env!("CARGO_PKG_VERSION")),
+            )
+            .send()
+            .ok()
+            .filter(|x| x.is_success())
+            .and_then(|x| x.bytes().ok());
+
+        match fetched {
+            Some(x) => {
+                // The tile is still served if the cache can't be written
+                if std::fs::create_dir_all(path.parent().unwrap()).is_ok() {
+                    std::fs::write(&path, &x).ok();
+                }
+                Ok(x)
+            }
+            // Serve an outdated tile rather than none when offline
+            None => std::fs::read(&path)
+                .map_err(|_| Error::BadServerResponse("Failed to fetch map tile")),
+        }
+    }
+}
+
+/// Guesses the content type of a tile image from its first bytes.
+fn content_type(image: &[u8]) -> &'static str {
+    match image {
+        [0xff, 0xd8, ..] => "image/jpeg",
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => "image/webp",
+        _ => "image/png",
+    }
+}